@@ -0,0 +1,74 @@
+use std::{hash::RandomState, num::NonZero, time::Instant};
+
+use anyhow::Result;
+use gxhash::GxBuildHasher;
+
+use crate::{RunState, config::Config, scan, visitor::StatVisitor};
+
+/// How much of each input is aggregated per hasher.
+const SAMPLE_BYTES: usize = 64 << 20;
+
+/// The hashers the station map can be built with.
+#[derive(Clone, Copy)]
+enum Hasher {
+    Gx,
+    Sip,
+}
+
+impl Hasher {
+    const ALL: [Self; 2] = [Self::Gx, Self::Sip];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Gx => "gxhash",
+            Self::Sip => "std (SipHash)",
+        }
+    }
+
+    /// Aggregates `inputs` with this hasher, returning how many stations were found.
+    fn aggregate(self, inputs: &[&[u8]], parts: NonZero<usize>, config: &Config) -> Result<usize> {
+        let run = &RunState::new(config);
+        Ok(match self {
+            Self::Gx => scan::<StatVisitor<GxBuildHasher>>(inputs, parts, config, run)?
+                .visitor
+                .stations
+                .len(),
+            Self::Sip => scan::<StatVisitor<RandomState>>(inputs, parts, config, run)?
+                .visitor
+                .stations
+                .len(),
+        })
+    }
+}
+
+/// Times a full aggregation of the start of each input with every hasher and prints the results.
+pub fn bench_hashers(inputs: &[&[u8]], parts: NonZero<usize>, config: &Config) -> Result<()> {
+    let sample = inputs
+        .iter()
+        .map(|data| sample(data, config))
+        .collect::<Vec<_>>();
+    let bytes = sample.iter().map(|data| data.len()).sum::<usize>();
+    println!("Sampled {bytes} bytes");
+    for hasher in Hasher::ALL {
+        let start = Instant::now();
+        let stations = hasher.aggregate(&sample, parts, config)?;
+        println!(
+            "{}: {:.3?} ({stations} stations)",
+            hasher.name(),
+            start.elapsed()
+        );
+    }
+    Ok(())
+}
+
+/// The first [`SAMPLE_BYTES`] of `data`, extended to the end of the record it cuts into.
+fn sample<'a>(data: &'a [u8], config: &Config) -> &'a [u8] {
+    let Some(rest) = data.get(SAMPLE_BYTES..) else {
+        return data;
+    };
+    let delim = &config.record_delim;
+    let end = delim
+        .find(rest)
+        .map_or(data.len(), |idx| SAMPLE_BYTES + idx + delim.len());
+    &data[..end]
+}
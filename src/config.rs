@@ -0,0 +1,855 @@
+use std::{
+    ffi::OsString,
+    mem,
+    num::NonZero,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail};
+use gxhash::HashSet;
+
+use crate::{
+    deadline::Deadline, delimiter::Delimiter, error::ParseError, error_log::ErrorLog,
+    input::read_roster, parse_decimal, split_line,
+};
+
+/// What to do with a line that can't be parsed.
+pub enum MalformedPolicy {
+    /// Fail the whole run.
+    Abort,
+    /// Skip the line and count it.
+    Skip,
+    /// Let a hook decide per line. It's only ever called for lines that fail to parse, so clean
+    /// data runs exactly as fast as under the other policies.
+    Custom(ErrorHook),
+}
+
+/// Decides what to do with a line that can't be parsed, given the line, why it failed and the
+/// config to split it with.
+pub type ErrorHook =
+    Box<dyn for<'a> Fn(&'a [u8], &ParseError, &Config) -> Recovery<'a> + Send + Sync>;
+
+/// What happens to a line that can't be parsed.
+pub enum Recovery<'a> {
+    /// Fail the whole run with the line's error.
+    Abort,
+    /// Skip the line and count it as malformed.
+    Skip,
+    /// Aggregate this station and value, in tenths, as if the line had held them.
+    Fix(&'a [u8], i16),
+}
+
+impl MalformedPolicy {
+    /// What to do with `line`, which failed to parse with `err`.
+    pub fn recover<'a>(&self, line: &'a [u8], err: &ParseError, config: &Config) -> Recovery<'a> {
+        match self {
+            Self::Abort => Recovery::Abort,
+            Self::Skip => Recovery::Skip,
+            Self::Custom(hook) => hook(line, err, config),
+        }
+    }
+}
+
+/// How the per-station results are written.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// The reference implementation's `{name=min/mean/max, ...}` map.
+    Text,
+    /// One JSON object per station per line.
+    Ndjson,
+    /// One tab-separated line of name, min, mean and max per station.
+    Tsv,
+    /// A MessagePack map from each name to an array of its min, mean and max.
+    Msgpack,
+    /// Names and min, mean and max in aligned columns, for reading in a terminal.
+    Table,
+}
+
+/// How many chunks each input is split into.
+#[derive(Clone, Copy)]
+pub enum ChunkCount {
+    /// Exactly this many, so the chunk boundaries don't depend on the machine.
+    Fixed(NonZero<usize>),
+    /// This many per core. Smaller chunks let rayon even out skewed data, at some cost per chunk.
+    PerCore(NonZero<usize>),
+    /// As many as it takes to keep chunks near this many bytes, so the boundaries only depend on
+    /// each input's length and not on how many cores the machine has.
+    Size(NonZero<usize>),
+}
+
+/// Decimal places shown for each column of a station's stats.
+#[derive(Clone, Copy)]
+pub struct Precision {
+    pub min: u32,
+    pub mean: u32,
+    pub max: u32,
+    /// Decimal places of the parsed values themselves, which every column is rescaled from: 1 for
+    /// tenths, or 2 for hundredths when `--auto-scale` finds them.
+    pub scale: u32,
+}
+
+impl Precision {
+    /// Enough to fit every value in an `i64` while scaling.
+    const MAX_PLACES: u32 = 9;
+}
+
+/// One place each, as the reference implementation prints.
+impl Default for Precision {
+    fn default() -> Self {
+        Self {
+            min: 1,
+            mean: 1,
+            max: 1,
+            scale: 1,
+        }
+    }
+}
+
+/// What `--help` prints.
+pub const USAGE: &str = "\
+Aggregates min/mean/max per station from `station;value` lines.
+
+Usage: brc [OPTIONS] [PATH]...
+       brc fix-output REPORT [OPTIONS]
+       brc verify --reference REPORT [OPTIONS] [PATH]...
+       brc generate --rows N [--stations N] [--seed N] [-o FILE]
+
+Paths can be files, directories to walk, or - for stdin. Anything that can't be mapped, like a
+pipe, is read as a stream. With no paths, ./measurements.txt is read.
+
+Inputs:
+  --paths-from FILE            Also read paths from FILE, one per line
+  --extension EXT              Only take files with this extension from directories
+  --follow-symlinks            Follow symlinks when walking directories
+  --hidden                     Include hidden files and directories
+  --max-open-files N           Map at most N files at once, aggregating the rest in waves
+  --input-buffer-size BYTES    Read streams in blocks of this size (default 64 MiB)
+  --preload                    Touch every page of the inputs before processing them
+  --header                     Skip the first record of each input
+  --range START,LEN            Only aggregate this byte range of the single input
+
+Records:
+  --record-delim BYTES         Record delimiter (default \\n); accepts \\0 \\n \\r \\t \\\\
+  --separator BYTES            Field separator (default ;)
+  --value-first                Records are value;station
+  --name-column N              Take the station from field N, counting from 0
+  --value-column N             Take the value from field N of those other than the station
+  --fixed-width NAME,VALUE     Slice records at fixed widths instead of splitting them
+  --max-name-len N             Treat names longer than N bytes as malformed
+  --sorted-input               The input is sorted by station
+
+Values:
+  --tolerant                   Accept any plain decimal, rounded to tenths
+  --integer-tenths             Read values as integers already in tenths
+  --auto-scale                 Detect one or two decimal places from the inputs
+  --strip-unit                 Ignore a trailing unit like the C in 12.3C
+  --fahrenheit-to-celsius      Convert F values to Celsius (needs --strip-unit)
+  --ignore-value V[,V...]      Drop readings equal to any of these sentinels
+
+Malformed lines:
+  --skip-invalid               Skip and count malformed lines instead of failing
+  --invalid-as V               Use V for values that don't parse
+  --errors-to FILE             Write skipped lines to FILE
+  --require-utf8               Fail on names that aren't UTF-8, or drop them when skipping
+
+Filtering and sampling:
+  --only NAME[,NAME...]        Only aggregate these stations
+  --exclude NAME[,NAME...]     Skip these stations
+  --min-count N                Leave out stations with fewer than N observations
+  --group-by-prefix DELIM,N    Roll stations up to their name before the Nth DELIM
+  --limit-lines N              Stop after roughly N lines
+  --sample-rate P              Keep each line with probability P
+  --seed N                     Seed --sample-rate
+  --deadline SECONDS           Report whatever was aggregated once this passes
+  --seed-stations FILE         Pre-fill each chunk's map with these stations
+  --keep-unseen                Report seeded stations that were never observed
+
+Output:
+  --output FILE                Write the report to FILE instead of stdout
+  --mmap-output                Write --output through a memory map
+  --format FORMAT              text, ndjson, tsv, msgpack or table (default text)
+  --precision N | MIN,MEAN,MAX Decimal places for each column (default 1)
+  --no-sort                    Leave stations in map order
+  --summary                    Print the aggregate of every station after the text report
+  --stations-only              Print only the station names
+  --first-last                 Also report each station's first and last value
+  --band-width W               Add the band of width W each mean falls in (ndjson only)
+  --time-window SECONDS        Aggregate per window of the timestamp field, as ndjson
+  --timestamp-column N         Field holding the timestamp (default 1)
+
+Snapshots:
+  --save-partial FILE          Write the result as a snapshot instead of reporting it
+  --combine                    Treat the inputs as snapshots and report them merged
+  --merge-into FILE            Merge the result into the snapshot in FILE and write it back
+
+Checks and other modes:
+  --count-only                 Only count lines and distinct stations
+  --cardinality                Only estimate the number of distinct stations
+  --validate                   Only check that every line parses
+  --expect-stations FILE       Fail if any station listed in FILE is missing
+  --fail-on-empty              Fail if there are no lines
+  --report-similar             List names that only differ by whitespace or case
+  --report-anomalies N         List the N stations with the widest spread
+  --emit-plan json             Print the chunk byte ranges instead of processing them
+  --bench-hashers              Time each available hasher on a sample
+
+Performance and diagnostics:
+  --threads N                  Run N worker threads
+  --chunks N | Nx              Split each input into N chunks, or N per core
+  --repeatable-chunking BYTES  Split inputs into chunks of about this size
+  --max-stations N             Fail once a chunk sees more than N stations
+  --quiet                      Leave out progress lines on stderr
+  --map-stats                  Print station map sizes and growth
+  --per-chunk-stats            Print each chunk's size, lines and stations
+  --debug-merge                Print how many chunks were merged into each station
+  --profile-parse              Print how long parsing and merging took
+  -h, --help                   Print this message
+";
+
+/// Options controlling a run, parsed from the command line.
+pub struct Config {
+    /// Files or directories to aggregate as one dataset.
+    pub paths: Vec<PathBuf>,
+    /// Files listing further paths to aggregate, one per line.
+    pub paths_from: Vec<PathBuf>,
+    /// Only take files with this extension when walking directories.
+    pub extension: Option<OsString>,
+    /// Follow symlinks when walking directories.
+    pub follow_symlinks: bool,
+    /// Include hidden files and directories when walking directories.
+    pub hidden: bool,
+    /// Touch every page of the inputs before processing them, so timings exclude cold reads.
+    pub preload: bool,
+    /// Map at most this many files at once, aggregating the rest in later waves.
+    pub max_open_files: Option<NonZero<usize>>,
+    /// Read streamed inputs, like stdin, in blocks of about this many bytes rather than 64 MiB.
+    /// Two blocks are held at once, so memory grows with it, while small blocks spend more of the
+    /// run waiting on reads and merging each block's stations than aggregating.
+    pub input_buffer_size: Option<NonZero<usize>>,
+    /// Write the report here instead of stdout.
+    pub output: Option<PathBuf>,
+    /// Write `output` through a memory map rather than buffered writes.
+    pub mmap_output: bool,
+    /// Only estimate the number of distinct stations, with a HyperLogLog sketch.
+    pub cardinality: bool,
+    /// Rewrite the report in this file with the current ordering and rounding instead of
+    /// aggregating anything, from `brc fix-output FILE`.
+    pub fix_output: Option<PathBuf>,
+    /// Only count lines and distinct stations, without parsing any values.
+    pub count_only: bool,
+    /// Only check that every line parses, reporting the invalid ones.
+    pub validate: bool,
+    /// Print the byte ranges of the chunks each input would be split into, as JSON, instead of
+    /// processing them.
+    pub emit_plan: bool,
+    /// Time the aggregation of a sample with each available hasher instead of reporting.
+    pub bench_hashers: bool,
+    /// Skip every station not in this set.
+    pub only: Option<HashSet<Box<[u8]>>>,
+    /// Skip every station in this set, after applying `only`.
+    pub exclude: Option<HashSet<Box<[u8]>>>,
+    /// What to do with lines that can't be parsed.
+    pub on_malformed: MalformedPolicy,
+    /// Where to write the lines skipped under [`MalformedPolicy`].
+    pub error_log: Option<ErrorLog>,
+    /// Fail if any station name isn't valid UTF-8, or drop those stations when skipping
+    /// malformed lines.
+    pub require_utf8: bool,
+    /// Print stations in whatever order the merged map holds them, which is unspecified and
+    /// differs between runs, instead of sorting them.
+    pub no_sort: bool,
+    /// Print the aggregate of every station after the map. Only the text report has one.
+    pub summary: bool,
+    /// Compare the report with the one in this file instead of writing it, from
+    /// `brc verify --reference FILE`.
+    pub verify: Option<PathBuf>,
+    /// Only aggregate this byte range, given as a start and a length, of the single input. The
+    /// range must start at a record, as the ranges from `--emit-plan` do.
+    pub range: Option<(usize, usize)>,
+    /// Write the result as a snapshot to this path instead of reporting it, for `--combine`.
+    pub save_partial: Option<PathBuf>,
+    /// Treat the inputs as snapshots from `--save-partial` and report them merged.
+    pub combine: bool,
+    /// Merge the result with the snapshot at this path, if there is one, and write it back.
+    pub merge_into: Option<PathBuf>,
+    /// How the per-station results are written.
+    pub format: OutputFormat,
+    /// Decimal places shown for each of min, mean and max.
+    pub precision: Precision,
+    /// Print only the station names, one per line, instead of the report.
+    pub stations_only: bool,
+    /// Separates records, `\n` unless overridden.
+    pub record_delim: Delimiter,
+    /// Separates the station from the value within a record, `;` unless overridden.
+    pub separator: Delimiter,
+    /// Skip the first record of each input, which names its columns rather than holding data.
+    pub header: bool,
+    /// Treat an input with no lines as an error instead of printing `{}`.
+    pub fail_on_empty: bool,
+    /// Widths of the name and value fields for records with no separator. Names are padded with
+    /// trailing spaces.
+    pub fixed_width: Option<(usize, usize)>,
+    /// Stop processing lines once this passes and report whatever was aggregated by then, which
+    /// covers an unspecified part of each chunk.
+    pub deadline: Option<Deadline>,
+    /// How many chunks to split each input into, one per core if not given.
+    pub chunks: Option<ChunkCount>,
+    /// Run this many worker threads, and count them as the cores for `chunks`, instead of one per
+    /// core. Ignored without the `rayon` feature.
+    pub threads: Option<NonZero<usize>>,
+    /// Leave out the progress lines and warnings on stderr, like the chunk sizes, line counts and
+    /// refused mmap advice.
+    pub quiet: bool,
+    /// Print [`USAGE`] instead of running. Everything after `--help` is left unparsed.
+    pub help: bool,
+    /// Print the size of the merged station map and how often the maps grew.
+    pub map_stats: bool,
+    /// Print the size, line count and station count of each chunk after processing it.
+    pub per_chunk_stats: bool,
+    /// Print, for each station, how many chunks' stats were merged into it.
+    pub debug_merge: bool,
+    /// Print how long processing the chunks took next to how long merging their results did.
+    pub profile_parse: bool,
+    /// The input is sorted by station, so each chunk can aggregate runs of lines for one station
+    /// without hashing them.
+    pub sorted_input: bool,
+    /// Fail once any chunk has seen more distinct stations than this, before a runaway number of
+    /// them, like names with timestamps glued on, can exhaust memory.
+    pub max_stations: Option<usize>,
+    /// Treat lines whose station name is longer than this many bytes as malformed.
+    pub max_name_len: Option<usize>,
+    /// Detect from the start of the first input whether values have one or two decimal places,
+    /// instead of requiring one.
+    pub auto_scale: bool,
+    /// Accept any plain decimal as a value instead of only the challenge's `-99.9` format.
+    pub tolerant: bool,
+    /// Read each value as a signed integer already in tenths, like `123` for `12.3`.
+    pub integer_tenths: bool,
+    /// Ignore a unit suffix of letters after each value, like the `C` in `12.3C`.
+    pub strip_unit: bool,
+    /// Convert values whose stripped unit is `F` from Fahrenheit to Celsius.
+    pub fahrenheit: bool,
+    /// Drop readings equal to any of these, in tenths, as if their lines weren't there.
+    pub ignore_values: Vec<i32>,
+    /// Records have several fields and the station name is this one, counting from 0.
+    pub name_column: Option<usize>,
+    /// Records have several fields and the value is this one of those other than the station
+    /// name, counting from 0.
+    pub value_column: Option<usize>,
+    /// Aggregate each station separately per window of this many seconds, by the timestamp in
+    /// `timestamp_column`, and write one NDJSON line per station and window.
+    pub time_window: Option<NonZero<u32>>,
+    /// The field, counting from 0, holding each record's timestamp in whole seconds under
+    /// `time_window`. Field 1 if not given.
+    pub timestamp_column: Option<usize>,
+    /// Records hold the value before the station name rather than after it.
+    pub value_first: bool,
+    /// Report stations whose names only differ by surrounding whitespace or case.
+    pub report_similar: bool,
+    /// Report this many stations with the widest spread between min and max.
+    pub report_anomalies: Option<usize>,
+    /// Fail if any station listed, one per line, in this file is missing from the result.
+    pub expect_stations: Option<PathBuf>,
+    /// Stations to put in each chunk's map before processing it, so high-cardinality inputs
+    /// don't keep growing the maps.
+    pub seed_stations: Vec<Box<[u8]>>,
+    /// Report seeded stations that were never observed, as `-/-/-`, instead of dropping them.
+    pub keep_unseen: bool,
+    /// Leave out stations with fewer observations than this.
+    pub min_count: Option<u32>,
+    /// Roll stations up to their name before the given occurrence of a delimiter.
+    pub group_by_prefix: Option<(u8, NonZero<usize>)>,
+    /// Stop after roughly this many lines. The cap covers every wave of files and stream block in a
+    /// run. Workers claim lines in batches, so runs can come up short of the cap by up to a batch
+    /// per chunk and which lines are counted depends on timing.
+    pub limit_lines: Option<u64>,
+    /// Keep each line with this probability. Means stay close, but min/max only cover the sample
+    /// so their range is underestimated.
+    pub sample_rate: Option<f64>,
+    /// Add the index of the band of this width, in tenths, that each station's displayed mean falls
+    /// in to the ndjson report.
+    pub band_width: Option<NonZero<u16>>,
+    /// Also report each station's first and last value in file order, for time-ordered data.
+    pub first_last: bool,
+    /// Seed for `sample_rate`, chosen from the clock if not given. A seed keeps the same lines of
+    /// the same inputs however they're chunked.
+    pub seed: Option<u64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            paths: Vec::new(),
+            paths_from: Vec::new(),
+            extension: None,
+            follow_symlinks: false,
+            hidden: false,
+            preload: false,
+            max_open_files: None,
+            input_buffer_size: None,
+            output: None,
+            mmap_output: false,
+            cardinality: false,
+            bench_hashers: false,
+            emit_plan: false,
+            fix_output: None,
+            count_only: false,
+            validate: false,
+            only: None,
+            exclude: None,
+            on_malformed: MalformedPolicy::Abort,
+            error_log: None,
+            require_utf8: false,
+            no_sort: false,
+            summary: false,
+            stations_only: false,
+            format: OutputFormat::Text,
+            precision: Precision::default(),
+            merge_into: None,
+            range: None,
+            save_partial: None,
+            combine: false,
+            verify: None,
+            record_delim: Delimiter::Byte(b'\n'),
+            separator: Delimiter::Byte(b';'),
+            header: false,
+            fail_on_empty: false,
+            fixed_width: None,
+            name_column: None,
+            value_column: None,
+            time_window: None,
+            timestamp_column: None,
+            value_first: false,
+            auto_scale: false,
+            tolerant: false,
+            integer_tenths: false,
+            ignore_values: Vec::new(),
+            strip_unit: false,
+            fahrenheit: false,
+            max_name_len: None,
+            max_stations: None,
+            sorted_input: false,
+            deadline: None,
+            chunks: None,
+            threads: None,
+            quiet: false,
+            help: false,
+            per_chunk_stats: false,
+            debug_merge: false,
+            profile_parse: false,
+            map_stats: false,
+            report_similar: false,
+            report_anomalies: None,
+            expect_stations: None,
+            seed_stations: Vec::new(),
+            keep_unseen: false,
+            min_count: None,
+            group_by_prefix: None,
+            limit_lines: None,
+            sample_rate: None,
+            band_width: None,
+            first_last: false,
+            seed: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut config = Self::default();
+        let mut invalid_as = None;
+        let mut chunk_size = None;
+        let mut args = args.into_iter().peekable();
+        if args.next_if(|arg| arg == "fix-output").is_some() {
+            let path = args.next().context("fix-output requires a report file")?;
+            config.fix_output = Some(path.into());
+        }
+        let verifying = args.next_if(|arg| arg == "verify").is_some();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .with_context(|| format!("{arg} requires a value"))
+            };
+            match arg.as_str() {
+                "-h" | "--help" => {
+                    config.help = true;
+                    return Ok(config);
+                }
+                "--paths-from" => config.paths_from.push(value()?.into()),
+                "--extension" => config.extension = Some(value()?.into()),
+                "--follow-symlinks" => config.follow_symlinks = true,
+                "--hidden" => config.hidden = true,
+                "--preload" => config.preload = true,
+                "--max-open-files" => config.max_open_files = Some(parse_value(&value()?)?),
+                "--input-buffer-size" => {
+                    config.input_buffer_size = Some(parse_value(&value()?)?);
+                }
+                "--output" => config.output = Some(value()?.into()),
+                "--mmap-output" => config.mmap_output = true,
+                "--cardinality" => config.cardinality = true,
+                "--count-only" => config.count_only = true,
+                "--validate" => config.validate = true,
+                "--emit-plan" => match value()?.as_str() {
+                    "json" => config.emit_plan = true,
+                    format => bail!("Unknown plan format {format:?}, expected json"),
+                },
+                "--bench-hashers" => config.bench_hashers = true,
+                "--only" => config.only = Some(parse_names(&value()?)),
+                "--exclude" => config.exclude = Some(parse_names(&value()?)),
+                "--errors-to" => config.error_log = Some(ErrorLog::create(Path::new(&value()?))?),
+                "--skip-invalid" => config.on_malformed = MalformedPolicy::Skip,
+                "--invalid-as" => {
+                    let value = value()?;
+                    let tenths = parse_decimal(value.trim().as_bytes())
+                        .and_then(|tenths| i16::try_from(tenths).ok())
+                        .with_context(|| format!("Invalid value {value:?}"))?;
+                    invalid_as = Some(tenths);
+                }
+                "--require-utf8" => config.require_utf8 = true,
+                "--no-sort" => config.no_sort = true,
+                "--summary" => config.summary = true,
+                "--stations-only" => config.stations_only = true,
+                "--format" => {
+                    config.format = match value()?.as_str() {
+                        "text" => OutputFormat::Text,
+                        "ndjson" => OutputFormat::Ndjson,
+                        "tsv" => OutputFormat::Tsv,
+                        "msgpack" => OutputFormat::Msgpack,
+                        "table" => OutputFormat::Table,
+                        format => bail!(
+                            "Unknown format {format:?}, expected text, ndjson, tsv, msgpack or table"
+                        ),
+                    }
+                }
+                "--precision" => config.precision = parse_precision(&value()?)?,
+                "--reference" if verifying => config.verify = Some(value()?.into()),
+                "--range" => config.range = Some(parse_pair(&value()?)?),
+                "--save-partial" => config.save_partial = Some(value()?.into()),
+                "--combine" => config.combine = true,
+                "--merge-into" => config.merge_into = Some(value()?.into()),
+                "--header" => config.header = true,
+                "--fail-on-empty" => config.fail_on_empty = true,
+                "--report-similar" => config.report_similar = true,
+                "--expect-stations" => config.expect_stations = Some(value()?.into()),
+                "--report-anomalies" => config.report_anomalies = Some(parse_value(&value()?)?),
+                "--record-delim" => config.record_delim = parse_delimiter(&value()?)?,
+                "--separator" => config.separator = parse_delimiter(&value()?)?,
+                "--deadline" => {
+                    let seconds = parse_value(&value()?)?;
+                    let duration = Duration::try_from_secs_f64(seconds)
+                        .with_context(|| format!("Invalid deadline {seconds}"))?;
+                    config.deadline = Some(Deadline::after(duration));
+                }
+                "--chunks" => {
+                    let count = value()?;
+                    config.chunks = Some(match count.strip_suffix('x') {
+                        Some(multiplier) => ChunkCount::PerCore(parse_value(multiplier)?),
+                        None => ChunkCount::Fixed(parse_value(&count)?),
+                    });
+                }
+                "--repeatable-chunking" => chunk_size = Some(parse_value(&value()?)?),
+                "--threads" => config.threads = Some(parse_value(&value()?)?),
+                "--quiet" => config.quiet = true,
+                "--map-stats" => config.map_stats = true,
+                "--per-chunk-stats" => config.per_chunk_stats = true,
+                "--debug-merge" => config.debug_merge = true,
+                "--profile-parse" => config.profile_parse = true,
+                "--sorted-input" => config.sorted_input = true,
+                "--max-stations" => config.max_stations = Some(parse_value(&value()?)?),
+                "--max-name-len" => config.max_name_len = Some(parse_value(&value()?)?),
+                "--ignore-value" => {
+                    for sentinel in value()?.split(',') {
+                        let tenths = parse_decimal(sentinel.trim().as_bytes())
+                            .with_context(|| format!("Invalid value {sentinel:?}"))?;
+                        config.ignore_values.push(tenths);
+                    }
+                }
+                "--strip-unit" => config.strip_unit = true,
+                "--fahrenheit-to-celsius" => config.fahrenheit = true,
+                "--tolerant" => config.tolerant = true,
+                "--integer-tenths" => config.integer_tenths = true,
+                "--auto-scale" => config.auto_scale = true,
+                "--name-column" => config.name_column = Some(parse_value(&value()?)?),
+                "--value-column" => config.value_column = Some(parse_value(&value()?)?),
+                "--value-first" => config.value_first = true,
+                "--time-window" => config.time_window = Some(parse_value(&value()?)?),
+                "--timestamp-column" => config.timestamp_column = Some(parse_value(&value()?)?),
+                "--fixed-width" => config.fixed_width = Some(parse_pair(&value()?)?),
+                "--seed-stations" => config.seed_stations = read_roster(Path::new(&value()?))?,
+                "--keep-unseen" => config.keep_unseen = true,
+                "--min-count" => config.min_count = Some(parse_value(&value()?)?),
+                "--limit-lines" => config.limit_lines = Some(parse_value(&value()?)?),
+                "--sample-rate" => {
+                    let rate = parse_value(&value()?)?;
+                    if !(0. ..=1.).contains(&rate) {
+                        bail!("{arg} must be between 0 and 1, got {rate}");
+                    }
+                    config.sample_rate = Some(rate);
+                }
+                "--first-last" => config.first_last = true,
+                "--band-width" => {
+                    let value = value()?;
+                    let tenths = parse_decimal(value.trim().as_bytes())
+                        .and_then(|tenths| u16::try_from(tenths).ok())
+                        .and_then(NonZero::new)
+                        .with_context(|| format!("Invalid band width {value:?}"))?;
+                    config.band_width = Some(tenths);
+                }
+                "--seed" => config.seed = Some(parse_value(&value()?)?),
+                "--group-by-prefix" => {
+                    let value = value()?;
+                    let (delim, level) = value.rsplit_once(',').with_context(|| {
+                        format!("Expected DELIM,LEVEL for {arg}, got {value:?}")
+                    })?;
+                    config.group_by_prefix = Some((parse_byte(delim)?, parse_value(level)?));
+                }
+                _ if arg.starts_with("--") => bail!("Unknown argument: {arg}"),
+                _ => config.paths.push(arg.into()),
+            }
+        }
+        if verifying && config.verify.is_none() {
+            bail!("verify requires --reference REPORT");
+        }
+        if config.fahrenheit && !config.strip_unit {
+            bail!("--fahrenheit-to-celsius needs --strip-unit");
+        }
+        if (config.name_column.is_some() || config.value_column.is_some())
+            && (config.value_first || config.fixed_width.is_some())
+        {
+            bail!(
+                "--name-column and --value-column can't be combined with --value-first or \
+                 --fixed-width"
+            );
+        }
+        if config.timestamp_column.is_some() && config.time_window.is_none() {
+            bail!("--timestamp-column needs --time-window");
+        }
+        if config.time_window.is_some() {
+            if config.format != OutputFormat::Ndjson {
+                bail!("--time-window needs --format ndjson");
+            }
+            if config.timestamp_column.unwrap_or(1) == config.name_column.unwrap_or(0) {
+                bail!("The timestamp and the station name can't be the same column");
+            }
+            for (set, flag) in [
+                (config.fixed_width.is_some(), "--fixed-width"),
+                (config.value_first, "--value-first"),
+                (config.combine, "--combine"),
+                (config.range.is_some(), "--range"),
+                (config.first_last, "--first-last"),
+            ] {
+                if set {
+                    bail!("--time-window can't be combined with {flag}");
+                }
+            }
+        }
+        if config.integer_tenths && config.tolerant {
+            bail!("--integer-tenths can't be combined with --tolerant");
+        }
+        if config.summary {
+            // Only the text report has a place for the line.
+            for (set, flag) in [
+                (config.stations_only, "--stations-only"),
+                (
+                    config.format != OutputFormat::Text,
+                    "--format other than text",
+                ),
+            ] {
+                if set {
+                    bail!("--summary can't be combined with {flag}");
+                }
+            }
+        }
+        if config.band_width.is_some() && config.format != OutputFormat::Ndjson {
+            bail!("--band-width needs --format ndjson");
+        }
+        if config.first_last {
+            // Snapshots and reports don't record the order of values, and rolled up stations have
+            // no single order to take it from.
+            for (set, flag) in [
+                (config.fix_output.is_some(), "fix-output"),
+                (config.combine, "--combine"),
+                (config.merge_into.is_some(), "--merge-into"),
+                (config.save_partial.is_some(), "--save-partial"),
+                (config.verify.is_some(), "verify"),
+                (config.group_by_prefix.is_some(), "--group-by-prefix"),
+                (
+                    matches!(config.format, OutputFormat::Msgpack | OutputFormat::Table),
+                    "--format msgpack or table",
+                ),
+            ] {
+                if set {
+                    bail!("--first-last can't be combined with {flag}");
+                }
+            }
+        }
+        if let Some(size) = chunk_size {
+            if config.chunks.is_some() {
+                bail!("--repeatable-chunking can't be combined with --chunks");
+            }
+            config.chunks = Some(ChunkCount::Size(size));
+        }
+        if let Some(value) = invalid_as {
+            // Lines that can't even be split fall back to whether --skip-invalid was given.
+            let fallback = mem::replace(&mut config.on_malformed, MalformedPolicy::Abort);
+            config.on_malformed =
+                MalformedPolicy::Custom(Box::new(move |line, err, config| {
+                    match (err, split_line(line, config)) {
+                        (ParseError::InvalidNumber { .. }, Ok((station, _))) => {
+                            Recovery::Fix(station, value)
+                        }
+                        _ => fallback.recover(line, err, config),
+                    }
+                }));
+        }
+        if config.auto_scale {
+            // These all assume tenths.
+            for (set, flag) in [
+                (config.tolerant, "--tolerant"),
+                (config.integer_tenths, "--integer-tenths"),
+                (invalid_as.is_some(), "--invalid-as"),
+                (config.fahrenheit, "--fahrenheit-to-celsius"),
+                (!config.ignore_values.is_empty(), "--ignore-value"),
+                (config.band_width.is_some(), "--band-width"),
+            ] {
+                if set {
+                    bail!("--auto-scale can't be combined with {flag}");
+                }
+            }
+        }
+        if config.paths.is_empty() && config.paths_from.is_empty() {
+            config.paths.push("./measurements.txt".into());
+        }
+        Ok(config)
+    }
+
+    /// The record delimiter and separator if every line is a plain record of two fields in the
+    /// challenge's format, split at single bytes, and no option has to look at each line or at the
+    /// order of values. Chunks of such runs are scanned without checking any.
+    pub(crate) fn plain_lines(&self) -> Option<(u8, u8)> {
+        let (&Delimiter::Byte(delim), &Delimiter::Byte(separator)) =
+            (&self.record_delim, &self.separator)
+        else {
+            return None;
+        };
+        let plain = self.deadline.is_none()
+            && self.limit_lines.is_none()
+            && self.sample_rate.is_none()
+            && self.fixed_width.is_none()
+            && self.name_column.is_none()
+            && self.value_column.is_none()
+            && !self.value_first
+            && self.max_name_len.is_none()
+            && self.only.is_none()
+            && self.exclude.is_none()
+            && !self.strip_unit
+            && !self.tolerant
+            && !self.integer_tenths
+            && self.precision.scale == 1
+            && self.ignore_values.is_empty()
+            && self.max_stations.is_none()
+            && !self.first_last;
+        plain.then_some((delim, separator))
+    }
+}
+
+/// Parses bytes written literally or with the escapes `\0`, `\n`, `\r`, `\t`, `\\`.
+fn parse_bytes(s: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let [first, tail @ ..] = rest {
+        rest = tail;
+        if *first != b'\\' {
+            bytes.push(*first);
+            continue;
+        }
+        let [escape, tail @ ..] = rest else {
+            bail!("Unfinished escape in {s:?}");
+        };
+        rest = tail;
+        bytes.push(match escape {
+            b'0' => b'\0',
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b't' => b'\t',
+            b'\\' => b'\\',
+            _ => bail!("Unknown escape \\{} in {s:?}", *escape as char),
+        });
+    }
+    Ok(bytes)
+}
+
+fn parse_byte(s: &str) -> Result<u8> {
+    match *parse_bytes(s)? {
+        [byte] => Ok(byte),
+        _ => bail!("Expected a single byte, got {s:?}"),
+    }
+}
+
+fn parse_delimiter(s: &str) -> Result<Delimiter> {
+    let bytes = parse_bytes(s)?;
+    if bytes.is_empty() {
+        bail!("Delimiters can't be empty");
+    }
+    Ok(Delimiter::new(&bytes))
+}
+
+/// Parses two comma-separated values, e.g. `32,5`.
+fn parse_pair<T: FromStr>(s: &str) -> Result<(T, T)>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let (a, b) = s
+        .split_once(',')
+        .with_context(|| format!("Expected two comma-separated values, got {s:?}"))?;
+    Ok((parse_value(a)?, parse_value(b)?))
+}
+
+/// Parses `MIN,MEAN,MAX` decimal places, or a single count used for all three.
+fn parse_precision(s: &str) -> Result<Precision> {
+    let places = s
+        .split(',')
+        .map(parse_value)
+        .collect::<Result<Vec<u32>>>()?;
+    let precision = match places[..] {
+        [all] => Precision {
+            min: all,
+            mean: all,
+            max: all,
+            ..Precision::default()
+        },
+        [min, mean, max] => Precision {
+            min,
+            mean,
+            max,
+            ..Precision::default()
+        },
+        _ => bail!("Expected one or three comma-separated values, got {s:?}"),
+    };
+    if [precision.min, precision.mean, precision.max]
+        .iter()
+        .any(|&places| places > Precision::MAX_PLACES)
+    {
+        bail!(
+            "At most {} decimal places are supported, got {s:?}",
+            Precision::MAX_PLACES
+        );
+    }
+    Ok(precision)
+}
+
+pub fn parse_value<T: FromStr>(s: &str) -> Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    s.trim()
+        .parse()
+        .with_context(|| format!("Invalid value {s:?}"))
+}
+
+/// Parses a comma-separated list of station names.
+fn parse_names(s: &str) -> HashSet<Box<[u8]>> {
+    s.split(',').map(|name| name.as_bytes().into()).collect()
+}
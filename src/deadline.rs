@@ -0,0 +1,34 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+/// A point in time after which chunks stop processing lines, leaving a partial result.
+pub struct Deadline {
+    at: Instant,
+    /// Whether any chunk has stopped because of it.
+    hit: AtomicBool,
+}
+
+impl Deadline {
+    pub fn after(duration: Duration) -> Self {
+        Self {
+            at: Instant::now() + duration,
+            hit: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether the deadline has passed, recording that something stopped for it if so.
+    pub fn expired(&self) -> bool {
+        let expired = Instant::now() >= self.at;
+        if expired {
+            self.hit.store(true, Ordering::Relaxed);
+        }
+        expired
+    }
+
+    /// Whether any chunk stopped early, which makes the result partial.
+    pub fn hit(&self) -> bool {
+        self.hit.load(Ordering::Relaxed)
+    }
+}
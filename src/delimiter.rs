@@ -0,0 +1,45 @@
+use memchr::{
+    memchr,
+    memmem::{self, Finder},
+    memrchr,
+};
+
+/// A field or record separator. Single bytes are found with `memchr`, longer ones with a
+/// prebuilt `memmem` searcher.
+pub enum Delimiter {
+    Byte(u8),
+    Bytes(Box<Finder<'static>>),
+}
+
+impl Delimiter {
+    pub fn new(bytes: &[u8]) -> Self {
+        match bytes {
+            [byte] => Self::Byte(*byte),
+            _ => Self::Bytes(Box::new(Finder::new(bytes).into_owned())),
+        }
+    }
+
+    #[inline]
+    pub fn find(&self, haystack: &[u8]) -> Option<usize> {
+        match self {
+            Self::Byte(byte) => memchr(*byte, haystack),
+            Self::Bytes(finder) => finder.find(haystack),
+        }
+    }
+
+    /// Where the last occurrence in `haystack` starts.
+    pub fn rfind(&self, haystack: &[u8]) -> Option<usize> {
+        match self {
+            Self::Byte(byte) => memrchr(*byte, haystack),
+            Self::Bytes(finder) => memmem::rfind(haystack, finder.needle()),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Self::Byte(_) => 1,
+            Self::Bytes(finder) => finder.needle().len(),
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use std::fmt::{self, Display};
+
+/// Why a line couldn't be parsed.
+#[derive(Debug)]
+pub enum ParseError {
+    /// The line has no separator between the station and the value.
+    MissingSeparator,
+    /// A fixed-width line ends before its name field does.
+    ShortName,
+    /// A fixed-width line ends before its value field does.
+    ShortValue,
+    /// The line has fewer fields than `--name-column` or `--value-column` needs. `index` counts
+    /// from 0 at the first field.
+    MissingField { index: usize },
+    /// The station name is longer than `--max-name-len` allows.
+    NameTooLong { len: usize },
+    /// The value isn't a number in a supported format.
+    InvalidNumber { bytes: Box<[u8]> },
+    /// A chunk has more distinct stations than `--max-stations` allows.
+    TooManyStations { limit: usize },
+    /// The `--timestamp-column` field isn't a whole number of seconds.
+    InvalidTimestamp { bytes: Box<[u8]> },
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingSeparator => f.write_str("No separator in line"),
+            Self::ShortName => f.write_str("Line shorter than its name field"),
+            Self::ShortValue => f.write_str("Line shorter than its value field"),
+            Self::MissingField { index } => write!(f, "No field {index} in line"),
+            Self::NameTooLong { len } => write!(f, "Station name is {len} bytes long"),
+            Self::InvalidNumber { bytes } => {
+                write!(f, "invalid number format: \"{}\"", bytes.escape_ascii())
+            }
+            Self::TooManyStations { limit } => {
+                write!(
+                    f,
+                    "More than {limit} distinct stations, the --max-stations limit"
+                )
+            }
+            Self::InvalidTimestamp { bytes } => {
+                write!(f, "Invalid timestamp \"{}\"", bytes.escape_ascii())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
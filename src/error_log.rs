@@ -0,0 +1,46 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use anyhow::{Context, Result};
+
+/// A file collecting the lines skipped as malformed, shared by every chunk.
+///
+/// Only malformed lines take the lock, so it stays off the hot path.
+pub struct ErrorLog {
+    /// The writer, or the first error it returned. Nothing more is written after an error.
+    writer: Mutex<io::Result<BufWriter<File>>>,
+}
+
+impl ErrorLog {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create error log {}", path.display()))?;
+        Ok(Self {
+            writer: Mutex::new(Ok(BufWriter::new(file))),
+        })
+    }
+
+    /// Appends `line`, followed by a newline.
+    pub fn log(&self, line: &[u8]) {
+        let mut writer = self.writer.lock().unwrap_or_else(|err| err.into_inner());
+        if let Ok(inner) = &mut *writer
+            && let Err(err) = inner.write_all(line).and_then(|()| inner.write_all(b"\n"))
+        {
+            *writer = Err(err);
+        }
+    }
+
+    /// Flushes the log, failing if any line couldn't be written.
+    pub fn finish(&self) -> Result<()> {
+        let mut writer = self.writer.lock().unwrap_or_else(|err| err.into_inner());
+        match &mut *writer {
+            Ok(inner) => inner.flush(),
+            Err(err) => Err(io::Error::new(err.kind(), err.to_string())),
+        }
+        .context("Failed to write error log")
+    }
+}
@@ -0,0 +1,62 @@
+use std::{fs, path::Path};
+
+use anyhow::{Context, Result, bail};
+use gxhash::{HashMap, HashMapExt};
+
+use crate::{Stat, config::Config, output::write_output, parse_decimal, verify::entries};
+
+/// Reads the `{name=min/mean/max, ...}` map of an earlier report at `path` and writes it again with
+/// the current ordering and rounding.
+///
+/// Each value is read back to tenths, rounding half up, and the mean becomes the total of a single
+/// observation. Digits the report already rounded away can't come back with a finer `--precision`.
+pub fn fix_output(path: &Path, config: &Config) -> Result<()> {
+    let bytes =
+        fs::read(path).with_context(|| format!("Failed to read report {}", path.display()))?;
+    let map = bytes
+        .split(|&byte| byte == b'\n')
+        .find(|line| line.starts_with(b"{"))
+        .with_context(|| format!("No {{...}} map in report {}", path.display()))?;
+    let mut stations = HashMap::new();
+    for (name, values) in entries(map)? {
+        let stat = parse_stat(values).with_context(|| {
+            format!(
+                "Station \"{}\" has invalid stats {}",
+                name.escape_ascii(),
+                values.escape_ascii()
+            )
+        })?;
+        if stations.insert(name, stat).is_some() {
+            bail!("Station \"{}\" appears twice", name.escape_ascii());
+        }
+    }
+    eprintln!("Read {} stations from {}", stations.len(), path.display());
+    write_output(&stations, config)
+}
+
+/// Parses `min/mean/max`, or `-/-/-` for a station with no observations.
+fn parse_stat(values: &[u8]) -> Option<Stat> {
+    if values == b"-/-/-" {
+        return Some(Stat::default());
+    }
+    let mut columns = values
+        .split(|&byte| byte == b'/')
+        .map(|column| parse_decimal(column).and_then(|tenths| i16::try_from(tenths).ok()));
+    let (Some(min), Some(mean), Some(max), None) = (
+        columns.next()?,
+        columns.next()?,
+        columns.next()?,
+        columns.next(),
+    ) else {
+        return None;
+    };
+    Some(Stat {
+        min,
+        max,
+        total: mean.into(),
+        count: 1,
+        // Reports don't show these, and `--first-last` refuses to rewrite them.
+        first: min,
+        last: max,
+    })
+}
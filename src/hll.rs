@@ -0,0 +1,57 @@
+use gxhash::gxhash64;
+
+/// Bits of the hash used to pick a register.
+const PRECISION: u32 = 14;
+const REGISTERS: usize = 1 << PRECISION;
+
+/// A HyperLogLog sketch estimating how many distinct byte strings have been inserted, using a
+/// fixed 16 KiB regardless of cardinality.
+pub struct HyperLogLog {
+    registers: Box<[u8; REGISTERS]>,
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        Self {
+            registers: Box::new([0; REGISTERS]),
+        }
+    }
+
+    pub fn insert(&mut self, item: &[u8]) {
+        let hash = gxhash64(item, 0);
+        let register = (hash >> (64 - PRECISION)) as usize;
+        // The guard bit caps the rank when the remaining bits are all zero.
+        let rest = (hash << PRECISION) | (1 << (PRECISION - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        self.registers[register] = self.registers[register].max(rank);
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        for (register, &rank) in self.registers.iter_mut().zip(other.registers.iter()) {
+            *register = (*register).max(rank);
+        }
+    }
+
+    pub fn estimate(&self) -> f64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1. + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&rank| 2f64.powi(-i32::from(rank)))
+            .sum();
+        let raw = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&rank| rank == 0).count();
+        // Small cardinalities are better served by linear counting of the empty registers.
+        if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        }
+    }
+
+    /// The relative standard error of [`Self::estimate`].
+    pub fn standard_error() -> f64 {
+        1.04 / (REGISTERS as f64).sqrt()
+    }
+}
@@ -0,0 +1,305 @@
+#[cfg(feature = "mmap")]
+use std::sync::Once;
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    fs, hint,
+    io::{self, Read},
+    num::NonZero,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::Instant,
+};
+
+use anyhow::{Context, Result, bail};
+#[cfg(feature = "mmap")]
+use memmap2::{Advice, Mmap};
+
+use crate::config::Config;
+
+/// How many bytes of a stream go in each block, unless `--input-buffer-size` says otherwise.
+const STREAM_BLOCK: usize = 64 << 20;
+
+/// Resolves the configured paths into the list of files to aggregate, walking any directories.
+///
+/// Files named explicitly are always included. Files found by walking a directory are filtered
+/// by extension and visibility, and symlinks are only followed when asked to. Entries are visited
+/// in name order so the file list is stable between runs.
+///
+/// Paths can be relative or absolute, or `-` for stdin. On Windows they can also be
+/// `\\server\share\...` UNC paths or verbatim `\\?\...` paths, and may be longer than the usual 260
+/// characters.
+pub fn collect_inputs(config: &Config) -> Result<Vec<PathBuf>> {
+    let mut paths = config.paths.clone();
+    for manifest in &config.paths_from {
+        paths.extend(read_manifest(manifest)?);
+    }
+    let mut files = Vec::new();
+    let mut visited = HashSet::new();
+    for path in &paths {
+        if path.as_os_str() == "-" {
+            files.push(path.clone());
+        } else if fs::metadata(native_path(path)?)
+            .with_context(|| format!("Failed to read metadata of {}", path.display()))?
+            .is_dir()
+        {
+            walk(path, config, &mut visited, &mut files)?;
+        } else {
+            files.push(path.clone());
+        }
+    }
+    Ok(files)
+}
+
+/// Reads one path per line, skipping blank lines and `#` comments.
+fn read_manifest(manifest: &Path) -> Result<Vec<PathBuf>> {
+    let contents = fs::read_to_string(manifest)
+        .with_context(|| format!("Failed to read path list {}", manifest.display()))?;
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// Reads a station roster, one name per line, skipping blank lines.
+pub fn read_roster(roster: &Path) -> Result<Vec<Box<[u8]>>> {
+    let contents = fs::read(roster)
+        .with_context(|| format!("Failed to read station roster {}", roster.display()))?;
+    Ok(contents
+        .split(|&byte| byte == b'\n')
+        .map(|name| name.strip_suffix(b"\r").unwrap_or(name))
+        .filter(|name| !name.is_empty())
+        .map(Box::from)
+        .collect())
+}
+
+fn walk(
+    dir: &Path,
+    config: &Config,
+    visited: &mut HashSet<PathBuf>,
+    files: &mut Vec<PathBuf>,
+) -> Result<()> {
+    // Following symlinks can lead back into a directory we're already inside.
+    let canonical = dir
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve {}", dir.display()))?;
+    if !visited.insert(canonical) {
+        return Ok(());
+    }
+    let mut entries = fs::read_dir(native_path(dir)?)
+        .with_context(|| format!("Failed to read directory {}", dir.display()))?
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to read directory {}", dir.display()))?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        if !config.hidden && entry.file_name().as_encoded_bytes().starts_with(b".") {
+            continue;
+        }
+        let mut file_type = entry
+            .file_type()
+            .with_context(|| format!("Failed to read file type of {}", path.display()))?;
+        if file_type.is_symlink() {
+            if !config.follow_symlinks {
+                continue;
+            }
+            file_type = fs::metadata(&path)
+                .with_context(|| format!("Failed to follow symlink {}", path.display()))?
+                .file_type();
+        }
+        if file_type.is_dir() {
+            walk(&path, config, visited, files)?;
+        } else if file_type.is_file()
+            && config
+                .extension
+                .as_ref()
+                .is_none_or(|ext| path.extension() == Some(ext))
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// `path` as the OS should be given it. On Windows, that is the absolute verbatim form,
+/// `\\?\C:\...` or `\\?\UNC\server\share\...`, which isn't limited to 260 characters. Verbatim
+/// paths skip Windows' own normalization, so `..` and `/` are resolved here first.
+#[cfg(windows)]
+fn native_path(path: &Path) -> Result<Cow<'_, Path>> {
+    let absolute = std::path::absolute(path)
+        .with_context(|| format!("Failed to resolve {}", path.display()))?;
+    let Some(full) = absolute.to_str() else {
+        // Only UTF-16 that isn't valid Unicode gets here, which std passes on as it is.
+        return Ok(Cow::Owned(absolute));
+    };
+    let verbatim = match full.strip_prefix(r"\\") {
+        Some(device) if device.starts_with(r"?\") || device.starts_with(r".\") => {
+            return Ok(Cow::Owned(absolute));
+        }
+        Some(share) => format!(r"\\?\UNC\{share}"),
+        None => format!(r"\\?\{full}"),
+    };
+    Ok(Cow::Owned(verbatim.into()))
+}
+/// Other platforms take paths as they are.
+#[cfg(not(windows))]
+fn native_path(path: &Path) -> Result<Cow<'_, Path>> {
+    Ok(Cow::Borrowed(path))
+}
+
+/// The contents of an input file, mapped when built with the `mmap` feature and read into memory
+/// otherwise.
+#[cfg(feature = "mmap")]
+pub type Input = Mmap;
+#[cfg(not(feature = "mmap"))]
+pub type Input = Vec<u8>;
+
+pub fn map_files(paths: &[PathBuf], config: &Config) -> Result<Vec<Input>> {
+    if let Some(path) = paths.iter().find(|path| is_stream(path)) {
+        bail!(
+            "{} can't be mapped, and only a plain aggregation reads streams",
+            path.display()
+        );
+    }
+    let inputs = paths
+        .iter()
+        .map(|path| map_file(path, config))
+        .collect::<Result<Vec<_>>>()?;
+    if config.preload {
+        let start = (!config.quiet).then(Instant::now);
+        let bytes = inputs.iter().map(|input| preload(input)).sum::<usize>();
+        if let Some(start) = start {
+            eprintln!("Preloaded {bytes} bytes in {:?}", start.elapsed());
+        }
+    }
+    Ok(inputs)
+}
+
+/// Reads a byte from every page of `data` so it's resident before processing starts, and returns
+/// its length.
+fn preload(data: &[u8]) -> usize {
+    const PAGE: usize = 4096;
+    let mut sum = 0u8;
+    for byte in data.iter().step_by(PAGE) {
+        sum = sum.wrapping_add(*byte);
+    }
+    hint::black_box(sum);
+    data.len()
+}
+
+#[cfg(not(feature = "mmap"))]
+fn map_file(path: &Path, _: &Config) -> Result<Input> {
+    fs::read(native_path(path)?)
+        .with_context(|| format!("Failed to read measurements file at {}", path.display()))
+}
+
+#[cfg(feature = "mmap")]
+fn map_file(path: &Path, config: &Config) -> Result<Input> {
+    let file = fs::File::open(native_path(path)?)
+        .with_context(|| format!("Failed to open measurements file at {}", path.display()))?;
+    // SAFTEY: This file won't be modified while in use.
+    let map = unsafe { Mmap::map(&file) }
+        .with_context(|| format!("Failed to mmap measurements file at {}", path.display()))?;
+    // Advice only changes how fast pages arrive, so a kernel that refuses it is no reason to fail.
+    // Huge pages are often disabled outright, which is only worth mentioning once per run.
+    if let Err(err) = map.advise(Advice::HugePage)
+        && !config.quiet
+    {
+        static HUGE_PAGES_UNAVAILABLE: Once = Once::new();
+        HUGE_PAGES_UNAVAILABLE
+            .call_once(|| eprintln!("Huge pages are unavailable, continuing without them: {err}"));
+    }
+    for advice in [Advice::Sequential, Advice::WillNeed] {
+        if let Err(err) = map.advise(advice)
+            && !config.quiet
+        {
+            eprintln!("Failed to advise kernel about mmap: advise {advice:?}: {err}");
+        }
+    }
+    Ok(map)
+}
+
+/// Whether `path` has to be read as a stream rather than mapped: `-` for stdin, or anything that
+/// isn't a regular file, like a pipe.
+pub fn is_stream(path: &Path) -> bool {
+    path.as_os_str() == "-"
+        || native_path(path)
+            .ok()
+            .and_then(|path| fs::metadata(path).ok())
+            .is_some_and(|metadata| !metadata.is_file())
+}
+
+/// Reads the stream at `path`, or stdin for `-`, in blocks of about `--input-buffer-size` bytes
+/// that each end just after a record delimiter, passing each to `process` in order. The next block
+/// is read on another thread while `process` runs, and the two buffers take turns.
+///
+/// A block only ends early at the end of the stream, whose last bytes come as they are even if
+/// no delimiter follows them. A record longer than a block makes its block grow to fit it.
+pub fn for_each_block(
+    path: &Path,
+    config: &Config,
+    mut process: impl FnMut(&[u8]) -> Result<()>,
+) -> Result<()> {
+    let mut reader: Box<dyn Read + Send> = if path.as_os_str() == "-" {
+        Box::new(io::stdin())
+    } else {
+        Box::new(
+            fs::File::open(native_path(path)?)
+                .with_context(|| format!("Failed to open {}", path.display()))?,
+        )
+    };
+    let delim = &config.record_delim;
+    let size = config.input_buffer_size.map_or(STREAM_BLOCK, NonZero::get);
+    let (full_tx, full_rx) = mpsc::sync_channel::<Vec<u8>>(1);
+    let (empty_tx, empty_rx) = mpsc::channel::<Vec<u8>>();
+    for _ in 0..2 {
+        empty_tx.send(Vec::with_capacity(size))?;
+    }
+    thread::scope(|scope| {
+        let reading = scope.spawn(move || -> io::Result<()> {
+            let mut carry = Vec::new();
+            // Stops early once the other side hangs up, which it only does on an error.
+            while let Ok(mut block) = empty_rx.recv() {
+                block.clear();
+                block.append(&mut carry);
+                let end = loop {
+                    let target = block.len() + size;
+                    let read = (&mut reader)
+                        .take((target - block.len()) as u64)
+                        .read_to_end(&mut block)?;
+                    if read == 0 || block.len() < target {
+                        break None;
+                    }
+                    if let Some(idx) = delim.rfind(&block) {
+                        break Some(idx + delim.len());
+                    }
+                };
+                let Some(end) = end else {
+                    if !block.is_empty() {
+                        let _ = full_tx.send(block);
+                    }
+                    return Ok(());
+                };
+                carry.extend_from_slice(&block[end..]);
+                block.truncate(end);
+                if full_tx.send(block).is_err() {
+                    return Ok(());
+                }
+            }
+            Ok(())
+        });
+        let processed = full_rx.iter().try_for_each(|block| -> Result<()> {
+            process(&block)?;
+            // The reader may have finished and stopped taking buffers.
+            let _ = empty_tx.send(block);
+            Ok(())
+        });
+        drop((full_rx, empty_tx));
+        let read = reading.join().expect("stream reader panicked");
+        processed?;
+        read.with_context(|| format!("Failed to read {}", path.display()))
+    })
+}
@@ -0,0 +1,2494 @@
+mod bench;
+mod config;
+mod deadline;
+mod delimiter;
+mod error;
+mod error_log;
+mod fix;
+mod generate;
+mod hll;
+mod input;
+mod output;
+mod rng;
+mod scale;
+mod snapshot;
+mod validate;
+mod verify;
+mod visitor;
+mod window;
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    iter, mem,
+    num::NonZero,
+    path::{Path, PathBuf},
+    slice,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result, bail};
+use gxhash::{HashMap, HashMapExt, HashSet, HashSetExt};
+use memchr::{memchr, memchr_iter};
+#[cfg(feature = "rayon")]
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
+
+pub use crate::{
+    config::{
+        ChunkCount, Config, ErrorHook, MalformedPolicy, OutputFormat, Precision, Recovery, USAGE,
+    },
+    delimiter::Delimiter,
+    error::ParseError,
+    output::{print, sort_stations},
+};
+use crate::{
+    deadline::Deadline,
+    hll::HyperLogLog,
+    input::{Input, collect_inputs, for_each_block, is_stream, map_files, read_roster},
+    output::{reference_order, write_output, write_plan, write_report},
+    rng::SplitMix64,
+    visitor::{LineVisitor, SortedVisitor, StatVisitor},
+};
+
+/// Runs the command line given by `args`, without the program name, as the `brc` binary does.
+pub fn run(args: impl IntoIterator<Item = String>) -> Result<()> {
+    let mut args = args.into_iter().peekable();
+    if args.next_if(|arg| arg == "generate").is_some() {
+        return generate::generate(args);
+    }
+    let mut config = Config::from_args(args)?;
+    if config.help {
+        print!("{USAGE}");
+        return Ok(());
+    }
+    if let Some(path) = &config.fix_output {
+        return fix::fix_output(path, &config);
+    }
+    let paths = collect_inputs(&config)?;
+    // Snapshots record their own scale.
+    if config.auto_scale && !config.combine {
+        config.precision.scale = scale::detect(&paths, &config)?;
+    }
+    let max_open = config
+        .max_open_files
+        .map_or(paths.len(), NonZero::get)
+        .max(1);
+
+    #[cfg(feature = "rayon")]
+    if let Some(threads) = config.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads.get())
+            .build_global()
+            .context("Failed to start the thread pool")?;
+    }
+    let (parts, layout) = layout(&config)?;
+    if !config.quiet {
+        eprintln!("{layout}");
+    }
+    if config.cardinality {
+        let mut sketch = HyperLogLog::new();
+        for wave in paths.chunks(max_open) {
+            let maps = map_files(wave, &config)?;
+            sketch.merge(&sketch_inputs(&slices(&maps, &config), parts, &config)?);
+        }
+        println!(
+            "Estimated stations: {:.0} (±{:.2}%)",
+            sketch.estimate(),
+            HyperLogLog::standard_error() * 100.
+        );
+        return Ok(());
+    }
+    if config.count_only {
+        let mut lines = 0;
+        let mut stations = HashSet::<Box<[u8]>>::new();
+        for wave in paths.chunks(max_open) {
+            let maps = map_files(wave, &config)?;
+            let (wave_lines, wave_stations) =
+                count_inputs(&slices(&maps, &config), parts, &config)?;
+            lines += wave_lines;
+            for station in wave_stations {
+                if !stations.contains(station) {
+                    stations.insert(station.into());
+                }
+            }
+        }
+        println!("lines={lines} stations={}", stations.len());
+        return Ok(());
+    }
+    if config.validate {
+        return validate::validate(&paths, max_open, parts, &config);
+    }
+    if let Some(width) = config.time_window {
+        return window::time_windows(&paths, max_open, parts, width, &config);
+    }
+    if config.emit_plan {
+        let mut plan = Vec::new();
+        for wave in paths.chunks(max_open) {
+            let maps = map_files(wave, &config)?;
+            for (path, data) in wave.iter().zip(&maps) {
+                for chunk in input_chunks(skip_header(data, &config), parts, &config) {
+                    let start = chunk.as_ptr() as usize - data.as_ptr() as usize;
+                    plan.push((path.as_path(), start, chunk.len()));
+                }
+            }
+        }
+        return write_plan(&plan);
+    }
+    if config.bench_hashers {
+        let maps = map_files(&paths[..max_open.min(paths.len())], &config)?;
+        return bench::bench_hashers(&slices(&maps, &config), parts, &config);
+    }
+    let maps;
+    let owned;
+    let previous;
+    let snapshots;
+    let mut result = if config.combine {
+        snapshots = paths
+            .iter()
+            .map(|path| {
+                snapshot::read(path)?
+                    .with_context(|| format!("Snapshot {} doesn't exist", path.display()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let (combined, scale) = combine(&paths, &snapshots)?;
+        config.precision.scale = scale;
+        combined
+    } else if let Some((start, len)) = config.range {
+        let [path] = &paths[..] else {
+            bail!("--range needs exactly one input file");
+        };
+        maps = map_files(slice::from_ref(path), &config)?;
+        let data = maps[0]
+            .get(start..start.saturating_add(len))
+            .with_context(|| {
+                format!("Range {start},{len} is past the end of {}", path.display())
+            })?;
+        // Only a range from the very start of the file can hold the header.
+        let data = if start == 0 {
+            skip_header(data, &config)
+        } else {
+            data
+        };
+        aggregate(&[data], parts, &config)?
+    } else if paths.len() <= max_open && !paths.iter().any(|path| is_stream(path)) {
+        maps = map_files(&paths, &config)?;
+        aggregate(&slices(&maps, &config), parts, &config)?
+    } else {
+        owned = aggregate_in_waves(&paths, max_open, parts, &config)?;
+        owned.borrow()
+    };
+    if let Some(log) = &config.error_log {
+        log.finish()?;
+    }
+    if !config.quiet {
+        eprintln!("Total lines processed: {}", result.total_lines);
+        if result.malformed_lines > 0 {
+            eprintln!("Malformed lines skipped: {}", result.malformed_lines);
+        }
+    }
+    if config.deadline.as_ref().is_some_and(Deadline::hit) {
+        eprintln!("Deadline passed: results only cover the lines processed before it");
+    }
+    if config.fail_on_empty && result.total_lines == 0 {
+        bail!("No lines were processed");
+    }
+    if let Some(path) = &config.save_partial {
+        return snapshot::write(path, &result.stations, config.precision.scale);
+    }
+    if let Some(path) = &config.merge_into {
+        previous = snapshot::read(path)?;
+        if let Some(bytes) = &previous {
+            let snapshot = snapshot::decode(bytes)
+                .with_context(|| format!("Failed to decode snapshot {}", path.display()))?;
+            let scale = config.precision.scale;
+            if snapshot.scale > scale {
+                bail!(
+                    "Snapshot {} has values with {} decimal places, which can't be merged into this \
+                     run's {scale}",
+                    path.display(),
+                    snapshot.scale
+                );
+            }
+            let stations = rescale_stations(snapshot.stations, snapshot.scale, scale)
+                .with_context(|| {
+                    format!(
+                        "Values in snapshot {} don't fit at {scale} decimal places",
+                        path.display()
+                    )
+                })?;
+            eprintln!(
+                "Merging {} stations from {}",
+                stations.len(),
+                path.display()
+            );
+            let mut merged = StatVisitor::from(mem::take(&mut result.stations));
+            merged.merge(stations.into());
+            result.stations = merged.stations;
+        }
+        snapshot::write(path, &result.stations, config.precision.scale)?;
+    }
+    if config.require_utf8 {
+        if matches!(config.on_malformed, MalformedPolicy::Skip) {
+            let before = result.stations.len();
+            result.stations.retain(|s, _| str::from_utf8(s).is_ok());
+            eprintln!(
+                "Dropped {} stations with names that aren't UTF-8",
+                before - result.stations.len()
+            );
+        } else if let Some(station) = result.stations.keys().find(|s| str::from_utf8(s).is_err()) {
+            bail!(
+                "Station name is not valid UTF-8: \"{}\"",
+                station.escape_ascii()
+            );
+        }
+    }
+    if let Some((delim, level)) = config.group_by_prefix {
+        result.stations = group_by_prefix(result.stations, delim, level);
+    }
+    if let Some(min_count) = config.min_count {
+        let before = result.stations.len();
+        result.stations.retain(|_, stat| stat.count() >= min_count);
+        eprintln!(
+            "Dropped {} stations with fewer than {min_count} observations",
+            before - result.stations.len()
+        );
+    }
+
+    if let Some(reference) = &config.verify {
+        return verify::verify(&result.stations, &config, reference);
+    }
+    write_output(&result.stations, &config)?;
+    if config.report_similar {
+        report_similar(result.stations.keys().copied());
+    }
+    if let Some(n) = config.report_anomalies {
+        report_anomalies(&result.stations, n, config.precision);
+    }
+    if let Some(roster) = &config.expect_stations {
+        check_roster(&result.stations, roster)?;
+    }
+    Ok(())
+}
+
+/// Fails if any station listed, one per line, in the file at `roster` is missing from `stations`,
+/// listing every missing one on stderr.
+fn check_roster(stations: &HashMap<&[u8], Stat>, roster: &Path) -> Result<()> {
+    let mut missing = 0;
+    for name in read_roster(roster)? {
+        if !stations.contains_key(&*name) {
+            eprintln!("Missing station: \"{}\"", name.escape_ascii());
+            missing += 1;
+        }
+    }
+    if missing > 0 {
+        bail!("{missing} stations from {} are missing", roster.display());
+    }
+    Ok(())
+}
+
+/// Rolls stations up to their name before the `level`th `delim`, merging stations that share it.
+/// Names with fewer delimiters are kept whole.
+fn group_by_prefix(
+    stations: HashMap<&[u8], Stat>,
+    delim: u8,
+    level: NonZero<usize>,
+) -> HashMap<&[u8], Stat> {
+    let mut groups = HashMap::<&[u8], Vec<Stat>>::new();
+    for (station, stat) in stations {
+        let prefix = memchr_iter(delim, station)
+            .nth(level.get() - 1)
+            .map_or(station, |idx| &station[..idx]);
+        groups.entry(prefix).or_default().push(stat);
+    }
+    groups
+        .into_iter()
+        .filter_map(|(prefix, stats)| Some((prefix, Stat::merge_all(stats)?)))
+        .collect()
+}
+
+/// Lists, on stderr, groups of stations whose names only differ by surrounding whitespace or
+/// ASCII case.
+fn report_similar<'a>(stations: impl Iterator<Item = &'a [u8]>) {
+    let mut groups = BTreeMap::<Vec<u8>, Vec<&[u8]>>::new();
+    for station in stations {
+        groups
+            .entry(station.trim_ascii().to_ascii_lowercase())
+            .or_default()
+            .push(station);
+    }
+    for group in groups.values_mut().filter(|group| group.len() > 1) {
+        group.sort_unstable_by(|a, b| reference_order(a, b));
+        let names = group
+            .iter()
+            .map(|name| format!("{:?}", String::from_utf8_lossy(name)))
+            .collect::<Vec<_>>();
+        eprintln!("Similar stations: {}", names.join(", "));
+    }
+}
+
+/// Lists, on stderr, the `n` stations with the widest spread between min and max, which tends to
+/// point at faulty sensors.
+fn report_anomalies(stations: &HashMap<&[u8], Stat>, n: usize, precision: Precision) {
+    let mut ranked = stations
+        .iter()
+        .filter(|(_, stat)| !stat.is_empty())
+        .map(|(&name, &stat)| (name, stat, i64::from(stat.max()) - i64::from(stat.min())))
+        .collect::<Vec<_>>();
+    ranked.sort_unstable_by(|(a, _, a_range), (b, _, b_range)| {
+        b_range.cmp(a_range).then_with(|| reference_order(a, b))
+    });
+    for (name, stat, range) in ranked.into_iter().take(n) {
+        eprintln!(
+            "Wide range: {:?} spans {} ({})",
+            String::from_utf8_lossy(name),
+            Fixed {
+                value: range,
+                places: precision.scale,
+            },
+            stat.display(precision)
+        );
+    }
+}
+
+/// Per-station results along with how many lines went into them.
+pub struct AggregateResult<'a> {
+    pub stations: HashMap<&'a [u8], Stat>,
+    pub total_lines: u64,
+    /// Lines skipped under [`MalformedPolicy::Skip`].
+    pub malformed_lines: u64,
+}
+
+/// An [`AggregateResult`] whose station names are copied out of the input.
+struct OwnedAggregateResult {
+    stations: HashMap<Box<[u8]>, Stat>,
+    total_lines: u64,
+    malformed_lines: u64,
+}
+
+impl OwnedAggregateResult {
+    fn borrow(&self) -> AggregateResult<'_> {
+        AggregateResult {
+            stations: self.stations.iter().map(|(k, &v)| (&**k, v)).collect(),
+            total_lines: self.total_lines,
+            malformed_lines: self.malformed_lines,
+        }
+    }
+
+    /// Adds `result`'s lines and stations to these, copying out any new station names.
+    fn absorb(&mut self, result: AggregateResult) {
+        self.total_lines += result.total_lines;
+        self.malformed_lines += result.malformed_lines;
+        for (station, stat) in result.stations {
+            match self.stations.get_mut(station) {
+                Some(existing) => existing.merge(&stat),
+                None => {
+                    self.stations.insert(station.into(), stat);
+                }
+            }
+        }
+    }
+}
+
+/// The contents of each input, past its header if there is one.
+fn slices<'a>(maps: &'a [Input], config: &Config) -> Vec<&'a [u8]> {
+    maps.iter().map(|map| skip_header(map, config)).collect()
+}
+
+/// `data` past its first record under `--header`, or all of it otherwise.
+fn skip_header<'a>(data: &'a [u8], config: &Config) -> &'a [u8] {
+    if !config.header {
+        return data;
+    }
+    let delim = &config.record_delim;
+    delim
+        .find(data)
+        .map_or(&[], |idx| &data[idx + delim.len()..])
+}
+
+/// Aggregates `paths` as one dataset, mapping at most `max_open` files at a time. Each wave of
+/// files is still split into `parts` chunks per file, and its stations are copied out before the
+/// files are unmapped. Streams, which can't be mapped, are read block by block in between, in
+/// the order they're given.
+fn aggregate_in_waves(
+    paths: &[PathBuf],
+    max_open: usize,
+    parts: NonZero<usize>,
+    config: &Config,
+) -> Result<OwnedAggregateResult> {
+    let mut owned = OwnedAggregateResult {
+        stations: HashMap::with_capacity(10_000),
+        total_lines: 0,
+        malformed_lines: 0,
+    };
+    let run = RunState::new(config);
+    for group in paths.split_inclusive(|path| is_stream(path)) {
+        let (files, stream) = match group.split_last() {
+            Some((last, files)) if is_stream(last) => (files, Some(last)),
+            _ => (group, None),
+        };
+        for wave in files.chunks(max_open) {
+            let maps = map_files(wave, config)?;
+            owned.absorb(aggregate_run(&slices(&maps, config), parts, config, &run)?);
+        }
+        if let Some(path) = stream {
+            aggregate_stream(path, &mut owned, parts, config, &run)?;
+        }
+    }
+    Ok(owned)
+}
+
+/// Aggregates the stream at `path`, or stdin for `-`, into `owned` one block at a time, each
+/// split into `parts` chunks like a file would be.
+fn aggregate_stream(
+    path: &Path,
+    owned: &mut OwnedAggregateResult,
+    parts: NonZero<usize>,
+    config: &Config,
+    run: &RunState,
+) -> Result<()> {
+    let mut first = true;
+    for_each_block(path, config, |block| {
+        // Only the first block starts with the header.
+        let block = if mem::take(&mut first) {
+            skip_header(block, config)
+        } else {
+            block
+        };
+        owned.absorb(aggregate_run(&[block], parts, config, run)?);
+        if let Some(limit) = config.max_stations
+            && owned.stations.len() > limit
+        {
+            return Err(ParseError::TooManyStations { limit })
+                .context("One or more chunks could not be processed");
+        }
+        Ok(())
+    })
+}
+
+/// Merges the snapshots written by `--save-partial`, counting each observation as a line.
+/// Snapshots with fewer decimal places are rescaled to the most any has, which is returned
+/// alongside.
+fn combine<'a>(paths: &[PathBuf], snapshots: &'a [Vec<u8>]) -> Result<(AggregateResult<'a>, u32)> {
+    let decoded = paths
+        .iter()
+        .zip(snapshots)
+        .map(|(path, bytes)| {
+            snapshot::decode(bytes)
+                .with_context(|| format!("Failed to decode snapshot {}", path.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let scale = decoded
+        .iter()
+        .map(|snapshot| snapshot.scale)
+        .max()
+        .unwrap_or(1);
+    let mut combined = StatVisitor::default();
+    for (path, snapshot) in paths.iter().zip(decoded) {
+        let stations =
+            rescale_stations(snapshot.stations, snapshot.scale, scale).with_context(|| {
+                format!(
+                    "Values in snapshot {} don't fit at {scale} decimal places",
+                    path.display()
+                )
+            })?;
+        combined.merge(stations.into());
+    }
+    let result = AggregateResult {
+        total_lines: combined
+            .stations
+            .values()
+            .map(|stat| u64::from(stat.count()))
+            .sum(),
+        stations: combined.stations,
+        malformed_lines: 0,
+    };
+    Ok((result, scale))
+}
+
+/// Moves every stat in `stations` from `from` to `to` decimal places, or `None` if any no longer
+/// fits.
+fn rescale_stations(
+    stations: HashMap<&[u8], Stat>,
+    from: u32,
+    to: u32,
+) -> Option<HashMap<&[u8], Stat>> {
+    stations
+        .into_iter()
+        .map(|(station, stat)| Some((station, stat.rescaled(from, to)?)))
+        .collect()
+}
+
+/// Estimates how many distinct stations `inputs` have, splitting each into `parts` chunks.
+fn sketch_inputs(inputs: &[&[u8]], parts: NonZero<usize>, config: &Config) -> Result<HyperLogLog> {
+    let chunks = inputs
+        .iter()
+        .flat_map(|data| input_chunks(data, parts, config))
+        .collect::<Vec<_>>();
+    let sketches = par_iter(chunks)
+        .map(|chunk| sketch_chunk(chunk, config))
+        .collect::<Result<Vec<_>, _>>()
+        .context("One or more chunks could not be processed")?;
+    Ok(sketches.into_iter().fold(HyperLogLog::new(), |mut a, b| {
+        a.merge(&b);
+        a
+    }))
+}
+
+/// Counts the lines of `inputs` and collects their distinct stations without parsing any values,
+/// splitting each input into `parts` chunks.
+fn count_inputs<'a>(
+    inputs: &[&'a [u8]],
+    parts: NonZero<usize>,
+    config: &Config,
+) -> Result<(u64, HashSet<&'a [u8]>)> {
+    let chunks = inputs
+        .iter()
+        .flat_map(|data| input_chunks(data, parts, config))
+        .collect::<Vec<_>>();
+    let counts = par_iter(chunks)
+        .map(|chunk| count_chunk(chunk, config))
+        .collect::<Result<Vec<_>, _>>()
+        .context("One or more chunks could not be processed")?;
+    Ok(counts.into_iter().fold(
+        (0, HashSet::new()),
+        |(lines, mut stations), (chunk_lines, chunk_stations)| {
+            stations.extend(chunk_stations);
+            (lines + chunk_lines, stations)
+        },
+    ))
+}
+
+/// How many chunks to split each input into, and a line describing that for stderr.
+fn layout(config: &Config) -> Result<(NonZero<usize>, String)> {
+    Ok(match config.chunks {
+        Some(ChunkCount::Fixed(chunks)) => (chunks, format!("Using {chunks} chunks per input")),
+        Some(ChunkCount::Size(size)) => {
+            let cores = cores(config)?;
+            (
+                cores,
+                format!("Using {cores} cores, chunks of about {size} bytes"),
+            )
+        }
+        Some(ChunkCount::PerCore(multiplier)) => {
+            let cores = cores(config)?;
+            let parts = cores
+                .checked_mul(multiplier)
+                .context("Too many chunks per input")?;
+            (
+                parts,
+                format!("Using {cores} cores, {multiplier} chunks each"),
+            )
+        }
+        None => {
+            let cores = cores(config)?;
+            (cores, format!("Using {cores} cores"))
+        }
+    })
+}
+
+/// How many chunks to split each input into: one per `--threads` or per core, or just one without
+/// the `rayon` feature since they'd be processed in turn anyway.
+fn cores(config: &Config) -> Result<NonZero<usize>> {
+    if !cfg!(feature = "rayon") {
+        return Ok(NonZero::<usize>::MIN);
+    }
+    match config.threads {
+        Some(threads) => Ok(threads),
+        None => std::thread::available_parallelism().context("Unable to get number of cores"),
+    }
+}
+
+/// Iterates over `items` on rayon's pool, or in order on this thread without the `rayon` feature.
+#[cfg(feature = "rayon")]
+fn par_iter<T: Send>(items: Vec<T>) -> rayon::vec::IntoIter<T> {
+    items.into_par_iter()
+}
+#[cfg(not(feature = "rayon"))]
+fn par_iter<T>(items: Vec<T>) -> std::vec::IntoIter<T> {
+    items.into_iter()
+}
+
+/// Aggregates `data`, a whole input already in memory, and formats its report as `run` would write
+/// it, for callers with no files to map, like WebAssembly in a browser. Only the options that shape
+/// aggregating and formatting apply. Fails if the report isn't UTF-8, as with `--format msgpack`.
+pub fn report(data: &[u8], config: &Config) -> Result<String> {
+    let (parts, _) = layout(config)?;
+    let result = aggregate(&[skip_header(data, config)], parts, config)?;
+    let mut report = Vec::new();
+    write_report(&mut report, &result.stations, config)?;
+    String::from_utf8(report).context("The report isn't valid UTF-8")
+}
+
+/// Aggregates every input as one dataset, splitting each into `parts` chunks. Inputs are taken as
+/// they are, so a `--header` line should already be cut off.
+pub fn aggregate<'a>(
+    inputs: &[&'a [u8]],
+    parts: NonZero<usize>,
+    config: &'a Config,
+) -> Result<AggregateResult<'a>> {
+    aggregate_run(inputs, parts, config, &RunState::new(config))
+}
+
+/// [`aggregate`] as one part of a run that goes on to aggregate more inputs with the same `run`.
+fn aggregate_run<'a>(
+    inputs: &[&'a [u8]],
+    parts: NonZero<usize>,
+    config: &'a Config,
+    run: &RunState,
+) -> Result<AggregateResult<'a>> {
+    let mut scanned = if config.sorted_input {
+        let Scanned {
+            lines,
+            malformed_lines,
+            visitor,
+        } = scan::<SortedVisitor>(inputs, parts, config, run)?;
+        Scanned {
+            lines,
+            malformed_lines,
+            visitor: visitor.finish(),
+        }
+    } else {
+        scan::<StatVisitor>(inputs, parts, config, run)?
+    };
+    if config.map_stats {
+        let stations = &scanned.visitor.stations;
+        eprintln!(
+            "Station map: {} stations, capacity {}, resized {} times",
+            stations.len(),
+            stations.capacity(),
+            scanned.visitor.resizes
+        );
+    }
+    if !config.seed_stations.is_empty() && !config.keep_unseen {
+        scanned.visitor.stations.retain(|_, stat| !stat.is_empty());
+    }
+    Ok(AggregateResult {
+        stations: scanned.visitor.stations,
+        total_lines: scanned.lines,
+        malformed_lines: scanned.malformed_lines,
+    })
+}
+
+/// What a run carries from one wave of files or stream block to the next. It lives apart from
+/// [`Config`] so that a config can be used for any number of runs.
+struct RunState {
+    /// Lines left under `--limit-lines`, counting down as workers claim them.
+    lines_left: Option<AtomicU64>,
+    /// How many chunks the run has scanned so far, which later waves of files and stream blocks
+    /// number their chunks on from.
+    chunks_scanned: AtomicU64,
+    /// How many input bytes the run has scanned so far, which later waves of files and stream
+    /// blocks count their lines' offsets on from.
+    bytes_scanned: AtomicU64,
+    /// `--sample-rate` with its seed, if lines are being sampled.
+    sampler: Option<Sampler>,
+}
+
+impl RunState {
+    fn new(config: &Config) -> Self {
+        let sampler = config.sample_rate.map(|rate| Sampler {
+            rate,
+            // Clocks panic on some targets, like wasm32-unknown-unknown, so only read one when
+            // asked to.
+            seed: config.seed.unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_nanos() as u64)
+            }),
+        });
+        Self {
+            lines_left: config.limit_lines.map(AtomicU64::new),
+            chunks_scanned: AtomicU64::new(0),
+            bytes_scanned: AtomicU64::new(0),
+            sampler,
+        }
+    }
+}
+
+/// Keeps each line with probability `rate`. Whether a line is kept only depends on the seed and
+/// where the line starts in the run, so a seed keeps the same lines however the input is split
+/// and whichever thread gets to them.
+#[derive(Clone, Copy)]
+struct Sampler {
+    rate: f64,
+    seed: u64,
+}
+
+impl Sampler {
+    /// Whether to keep the line that starts `offset` bytes into the run.
+    fn keeps(self, offset: u64) -> bool {
+        SplitMix64::new(self.seed ^ offset).next_f64() < self.rate
+    }
+}
+
+/// What a visitor made of some lines, and how many lines it was given.
+#[derive(Default)]
+struct Scanned<V> {
+    lines: u64,
+    malformed_lines: u64,
+    visitor: V,
+}
+
+impl<'a, V: LineVisitor<'a>> Scanned<V> {
+    /// Folds in what was scanned from later lines.
+    fn merge(&mut self, other: Self) {
+        self.lines += other.lines;
+        self.malformed_lines += other.malformed_lines;
+        self.visitor.merge(other.visitor);
+    }
+}
+
+/// Runs a `V` over each chunk of `inputs` in parallel, then merges them in input order.
+///
+/// Chunks are folded in file order rather than as they finish, so a visitor can rely on every
+/// merge bringing in later lines, as `--first-last` does.
+fn scan<'a, V: LineVisitor<'a>>(
+    inputs: &[&'a [u8]],
+    parts: NonZero<usize>,
+    config: &'a Config,
+    run: &RunState,
+) -> Result<Scanned<V>> {
+    // Each chunk comes with the offset of its first byte in the run.
+    let chunks = inputs
+        .iter()
+        .flat_map(|data| {
+            let base = run
+                .bytes_scanned
+                .fetch_add(data.len() as u64, Ordering::Relaxed);
+            input_chunks(data, parts, config)
+                .into_iter()
+                .map(move |chunk| {
+                    (
+                        base + (chunk.as_ptr() as usize - data.as_ptr() as usize) as u64,
+                        chunk,
+                    )
+                })
+        })
+        .collect::<Vec<_>>();
+    let first_index = run
+        .chunks_scanned
+        .fetch_add(chunks.len() as u64, Ordering::Relaxed);
+    let plain = config.plain_lines();
+    let start = config.profile_parse.then(Instant::now);
+    let results = par_iter(chunks)
+        .enumerate()
+        .map(|(index, (offset, chunk))| {
+            if !config.quiet {
+                eprintln!("Processing chunk {} bytes", chunk.len());
+            }
+            let position = first_index + index as u64;
+            let scanned = match plain {
+                Some(delims) => process_plain_chunk::<V>(chunk, config, delims)?,
+                None => process_chunk::<V>(chunk, offset, config, run)?,
+            };
+            if config.per_chunk_stats {
+                eprintln!(
+                    "Chunk {position}: {} bytes, {} lines, {} stations",
+                    chunk.len(),
+                    scanned.lines,
+                    scanned.visitor.stations()
+                );
+            }
+            Ok(scanned)
+        })
+        .collect::<Result<Vec<_>, ParseError>>()
+        .context("One or more chunks could not be processed")?;
+    let parsed = start.map(|start| start.elapsed());
+    if config.debug_merge {
+        report_fan_in(results.iter().map(|scanned| &scanned.visitor));
+    }
+
+    let start = config.profile_parse.then(Instant::now);
+    let merged = results
+        .into_iter()
+        .reduce(|mut a, b| {
+            a.merge(b);
+            a
+        })
+        .unwrap_or_default();
+    if let (Some(parsed), Some(start)) = (parsed, start) {
+        let merging = start.elapsed();
+        let total = (parsed + merging).as_secs_f64().max(f64::MIN_POSITIVE);
+        eprintln!(
+            "Parsing took {parsed:?} ({:.1}%), merging took {merging:?} ({:.1}%)",
+            parsed.as_secs_f64() / total * 100.,
+            merging.as_secs_f64() / total * 100.
+        );
+    }
+    Ok(merged)
+}
+
+/// Lists, on stderr and in reference order, how many of `visitors` saw each station, which is how
+/// many chunk stats get merged into it. A station split across K chunks shows K.
+fn report_fan_in<'a, 'v, V: LineVisitor<'a> + 'v>(visitors: impl Iterator<Item = &'v V>) {
+    for (station, chunks) in fan_in(visitors) {
+        eprintln!("Fan-in {chunks}: \"{}\"", station.escape_ascii());
+    }
+}
+
+/// How many of `visitors` saw each station, in reference order.
+fn fan_in<'a, 'v, V: LineVisitor<'a> + 'v>(
+    visitors: impl Iterator<Item = &'v V>,
+) -> Vec<(&'a [u8], u32)> {
+    let mut fan_in = HashMap::<&[u8], u32>::new();
+    for visitor in visitors {
+        for station in visitor.names() {
+            *fan_in.entry(station).or_default() += 1;
+        }
+    }
+    let mut fan_in = fan_in.into_iter().collect::<Vec<_>>();
+    fan_in.sort_unstable_by(|(a, _), (b, _)| reference_order(a, b));
+    fan_in
+}
+
+/// Splits an input into `parts` chunks, or into chunks of about the size given to
+/// `--repeatable-chunking`, whatever `parts` is.
+fn input_chunks<'a>(data: &'a [u8], parts: NonZero<usize>, config: &Config) -> Box<[&'a [u8]]> {
+    let parts = match config.chunks {
+        Some(ChunkCount::Size(size)) => {
+            NonZero::new(data.len().div_ceil(size.get())).unwrap_or(NonZero::<usize>::MIN)
+        }
+        _ => parts,
+    };
+    chunk_data(data, parts, &config.record_delim)
+}
+
+/// Splits `data` into at most `parts` non-empty chunks, each ending just after a `needle`.
+///
+/// Each search for a boundary starts `jump` bytes past the end of the previous chunk, and
+/// everything it scans becomes part of the new chunk, so no byte is scanned twice. Even a single
+/// enormous line costs one linear pass and comes back as one chunk.
+///
+/// Every chunk but the last ends in its own `needle`, so there can't be more chunks than that
+/// allows. `parts` is capped there first, which keeps a part count far above the number of records,
+/// as a small file on a many-core machine gets, from reserving room for chunks that can't exist.
+pub fn chunk_data<'a>(
+    data: &'a [u8],
+    parts: NonZero<usize>,
+    needle: &Delimiter,
+) -> Box<[&'a [u8]]> {
+    let parts = parts.min(NonZero::<usize>::MIN.saturating_add(data.len() / needle.len()));
+    let mut chunks = Vec::with_capacity(parts.get());
+    let jump = data.len() / parts;
+    let mut data = data;
+    while chunks.len() < parts.get() - 1
+        && data.len() > jump
+        && let Some(offset) = needle.find(&data[jump..])
+    {
+        let (chunk, rest) = data.split_at(jump + offset + needle.len());
+        chunks.push(chunk);
+        data = rest;
+    }
+    if !data.is_empty() {
+        chunks.push(data);
+    }
+    chunks.into_boxed_slice()
+}
+
+/// Iterates over the records of `data` that end in `delim`, leaving out lines [`is_record`] turns
+/// down. Mapped files, stream blocks and every mode split records here, or the same way in
+/// [`process_plain_chunk`], so they all agree on which lines count, wherever a chunk or block
+/// happens to start.
+fn records<'a>(mut data: &'a [u8], delim: &Delimiter) -> impl Iterator<Item = &'a [u8]> {
+    iter::from_fn(move || {
+        let idx = delim.find(data)?;
+        let line = &data[..idx];
+        data = &data[idx + delim.len()..];
+        Some(line)
+    })
+    .filter(|line| is_record(line))
+}
+
+/// Whether a line should be processed at all. Blank lines are skipped without being counted.
+fn is_record(line: &[u8]) -> bool {
+    !line.is_empty()
+}
+
+/// Splits a record into its station name and unparsed value.
+fn split_line<'a>(line: &'a [u8], config: &Config) -> Result<(&'a [u8], &'a [u8]), ParseError> {
+    let (station, value) = split_fields(line, config)?;
+    // A missing record delimiter can glue megabytes into one name, which would then be hashed and
+    // compared on every later line of its station.
+    if let Some(max) = config.max_name_len
+        && station.len() > max
+    {
+        return Err(ParseError::NameTooLong { len: station.len() });
+    }
+    Ok((station, value))
+}
+
+fn split_fields<'a>(line: &'a [u8], config: &Config) -> Result<(&'a [u8], &'a [u8]), ParseError> {
+    Ok(match config.fixed_width {
+        Some((name_len, value_len)) if config.value_first => {
+            let value = line.get(..value_len).ok_or(ParseError::ShortValue)?;
+            let name = line
+                .get(value_len..value_len + name_len)
+                .ok_or(ParseError::ShortName)?;
+            (name.trim_ascii_end(), value.trim_ascii())
+        }
+        Some((name_len, value_len)) => {
+            let name = line.get(..name_len).ok_or(ParseError::ShortName)?;
+            let value = line
+                .get(name_len..name_len + value_len)
+                .ok_or(ParseError::ShortValue)?;
+            (name.trim_ascii_end(), value.trim_ascii())
+        }
+        None if config.name_column.is_some() || config.value_column.is_some() => {
+            let name_index = config.name_column.unwrap_or(0);
+            // The value column skips over the name's.
+            let value_index = config.value_column.unwrap_or(0);
+            let value_index = value_index + usize::from(value_index >= name_index);
+            let (mut name, mut value) = (None, None);
+            let needed = name_index.max(value_index) + 1;
+            for (index, field) in fields(line, &config.separator).enumerate().take(needed) {
+                if index == name_index {
+                    name = Some(field);
+                } else if index == value_index {
+                    value = Some(field);
+                }
+            }
+            (
+                name.ok_or(ParseError::MissingField { index: name_index })?,
+                value.ok_or(ParseError::MissingField { index: value_index })?,
+            )
+        }
+        None => {
+            let idx = config
+                .separator
+                .find(line)
+                .ok_or(ParseError::MissingSeparator)?;
+            let (first, second) = (&line[..idx], &line[idx + config.separator.len()..]);
+            if config.value_first {
+                (second, first)
+            } else {
+                (first, second)
+            }
+        }
+    })
+}
+
+/// Splits `line` at every `separator`, so it always has at least one field.
+fn fields<'a>(mut line: &'a [u8], separator: &Delimiter) -> impl Iterator<Item = &'a [u8]> {
+    let mut done = false;
+    iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let Some(idx) = separator.find(line) else {
+            done = true;
+            return Some(line);
+        };
+        let field = &line[..idx];
+        line = &line[idx + separator.len()..];
+        Some(field)
+    })
+}
+
+/// Estimates how many distinct stations `data` has without aggregating any values.
+fn sketch_chunk(data: &[u8], config: &Config) -> Result<HyperLogLog, ParseError> {
+    let mut sketch = HyperLogLog::new();
+    for line in records(data, &config.record_delim) {
+        match split_line(line, config) {
+            Ok((station, _)) => sketch.insert(station),
+            Err(err) => match config.on_malformed.recover(line, &err, config) {
+                Recovery::Abort => return Err(err),
+                Recovery::Skip => {}
+                Recovery::Fix(station, _) => sketch.insert(station),
+            },
+        }
+    }
+    Ok(sketch)
+}
+
+/// Counts the lines of `data` and collects the stations they name, only splitting each line.
+fn count_chunk<'a>(
+    data: &'a [u8],
+    config: &Config,
+) -> Result<(u64, HashSet<&'a [u8]>), ParseError> {
+    let mut lines = 0;
+    let mut stations = HashSet::with_capacity(10_000);
+    for line in records(data, &config.record_delim) {
+        lines += 1;
+        let station = match split_line(line, config) {
+            Ok((station, _)) => station,
+            Err(err) => match config.on_malformed.recover(line, &err, config) {
+                Recovery::Abort => return Err(err),
+                Recovery::Skip => continue,
+                Recovery::Fix(station, _) => station,
+            },
+        };
+        if !filtered_out(station, config) {
+            stations.insert(station);
+        }
+    }
+    Ok((lines, stations))
+}
+
+/// How many lines a worker claims at once from a `--limit-lines` budget.
+const LINE_BATCH: u64 = 4096;
+
+/// Feeds the lines of `data`, which starts `offset` bytes into the run, to a fresh `V`. Under
+/// `--limit-lines`, lines are claimed from the run's budget in batches and processing stops once
+/// it runs out. Under `--sample-rate`, the run's sampler decides which lines are kept.
+fn process_chunk<'a, V: LineVisitor<'a>>(
+    data: &'a [u8],
+    offset: u64,
+    config: &'a Config,
+    run: &RunState,
+) -> Result<Scanned<V>, ParseError> {
+    let mut visitor = V::default();
+    for station in &config.seed_stations {
+        visitor.seed(station);
+    }
+    let mut total = 0;
+    let mut malformed = 0;
+    let mut claimed = 0;
+    for line in records(data, &config.record_delim) {
+        if let Some(deadline) = &config.deadline
+            && total % LINE_BATCH == 0
+            && deadline.expired()
+        {
+            break;
+        }
+        if let Some(remaining) = &run.lines_left {
+            if claimed == 0 {
+                let before = remaining
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |r| {
+                        Some(r.saturating_sub(LINE_BATCH))
+                    })
+                    .unwrap_or_default();
+                claimed = before.min(LINE_BATCH);
+                if claimed == 0 {
+                    break;
+                }
+            }
+            claimed -= 1;
+        }
+        total += 1;
+        if let Some(sampler) = run.sampler
+            && !sampler.keeps(offset + (line.as_ptr() as usize - data.as_ptr() as usize) as u64)
+        {
+            continue;
+        }
+        match parse_line(line, config) {
+            Ok(Some((station, value))) => visitor.visit(station, value),
+            Ok(None) => {}
+            Err(err) => malformed += recover_line(line, err, &mut visitor, config)?,
+        }
+        if let Some(limit) = config.max_stations
+            && visitor.stations() > limit
+        {
+            return Err(ParseError::TooManyStations { limit });
+        }
+    }
+    Ok(Scanned {
+        lines: total,
+        malformed_lines: malformed,
+        visitor,
+    })
+}
+
+/// [`process_chunk`] for runs where [`Config::plain_lines`] gives the record delimiter and
+/// separator. No option is checked per line and both delimiters are found with `memchr` directly,
+/// which keeps the default run about as fast as it was before any of those options existed.
+fn process_plain_chunk<'a, V: LineVisitor<'a>>(
+    mut data: &'a [u8],
+    config: &'a Config,
+    (delim, separator): (u8, u8),
+) -> Result<Scanned<V>, ParseError> {
+    let mut visitor = V::default();
+    for station in &config.seed_stations {
+        visitor.seed(station);
+    }
+    let mut total = 0;
+    let mut malformed = 0;
+    // The same records as `records` yields, split without going through a `Delimiter`.
+    while let Some(idx) = memchr(delim, data) {
+        let line = &data[..idx];
+        data = &data[idx + 1..];
+        if !is_record(line) {
+            continue;
+        }
+        total += 1;
+        let Some(idx) = memchr(separator, line) else {
+            malformed += recover_line(line, ParseError::MissingSeparator, &mut visitor, config)?;
+            continue;
+        };
+        match parse_number(&line[idx + 1..]) {
+            Ok(value) => visitor.visit_unordered(&line[..idx], value),
+            Err(err) => malformed += recover_line(line, err, &mut visitor, config)?,
+        }
+    }
+    Ok(Scanned {
+        lines: total,
+        malformed_lines: malformed,
+        visitor,
+    })
+}
+
+/// Handles a line that failed to parse with `err` as [`Config::on_malformed`] says, feeding a fixed
+/// one to `visitor`. Returns how many lines were skipped as malformed, 0 or 1.
+#[cold]
+fn recover_line<'a, V: LineVisitor<'a>>(
+    line: &'a [u8],
+    err: ParseError,
+    visitor: &mut V,
+    config: &'a Config,
+) -> Result<u64, ParseError> {
+    match config.on_malformed.recover(line, &err, config) {
+        Recovery::Abort => Err(err),
+        Recovery::Skip => {
+            if let Some(log) = &config.error_log {
+                log.log(line);
+            }
+            Ok(1)
+        }
+        Recovery::Fix(station, value) => {
+            if !filtered_out(station, config) {
+                visitor.visit(station, value);
+            }
+            Ok(0)
+        }
+    }
+}
+
+/// Parses a record into its station and value, or `None` if the station is filtered out.
+#[inline]
+fn parse_line<'a>(line: &'a [u8], config: &Config) -> Result<Option<(&'a [u8], i16)>, ParseError> {
+    let (station, value) = split_line(line, config)?;
+    if filtered_out(station, config) {
+        return Ok(None);
+    }
+    Ok(parse_reading(value, config)?.map(|value| (station, value)))
+}
+
+/// Parses the value field of a record, or `None` if it's one of the `--ignore-value` sentinels.
+#[inline]
+fn parse_reading(value: &[u8], config: &Config) -> Result<Option<i16>, ParseError> {
+    let (value, unit) = if config.strip_unit {
+        split_unit(value)
+    } else {
+        (value, &[][..])
+    };
+    let parsed = if config.tolerant {
+        parse_number_tolerant(value)
+    } else if config.integer_tenths {
+        parse_integer_tenths(value)
+    } else if config.precision.scale != 1 {
+        parse_fixed(value, config.precision.scale as usize)
+    } else {
+        parse_number(value)
+    };
+    if !config.ignore_values.is_empty() {
+        // Sentinels may lie outside the range a reading can take, like `-9999.0`, in which case the
+        // line is dropped rather than reported as malformed.
+        let tenths = match &parsed {
+            Ok(value) => Some(i32::from(*value)),
+            Err(_) if config.integer_tenths => {
+                parse_integer(value).and_then(|tenths| i32::try_from(tenths).ok())
+            }
+            Err(_) => parse_decimal(value),
+        };
+        if tenths.is_some_and(|tenths| config.ignore_values.contains(&tenths)) {
+            return Ok(None);
+        }
+    }
+    let mut value = parsed?;
+    if config.fahrenheit && unit == b"F" {
+        value = fahrenheit_to_celsius(value);
+    }
+    Ok(Some(value))
+}
+
+/// Whether `--only` or `--exclude` leave out `station`.
+#[inline]
+fn filtered_out(station: &[u8], config: &Config) -> bool {
+    config
+        .only
+        .as_ref()
+        .is_some_and(|only| !only.contains(station))
+        || config
+            .exclude
+            .as_ref()
+            .is_some_and(|exclude| exclude.contains(station))
+}
+
+/// Splits a value from its unit suffix, like the `C` in `12.3C`, which only ever holds letters.
+fn split_unit(value: &[u8]) -> (&[u8], &[u8]) {
+    let end = value
+        .iter()
+        .rposition(|byte| !byte.is_ascii_alphabetic())
+        .map_or(0, |idx| idx + 1);
+    value.split_at(end)
+}
+
+/// Converts tenths of a degree Fahrenheit to tenths of a degree Celsius, rounding half up.
+fn fahrenheit_to_celsius(tenths: i16) -> i16 {
+    // Tenths of a degree Fahrenheit fit in an `i16`, so tenths of a degree Celsius do too.
+    ((i32::from(tenths) - 320) * 10 + 9).div_euclid(18) as i16
+}
+
+/// Parses a value in the challenge's format, `-99.9` to `99.9` with exactly one decimal place, to
+/// tenths.
+// LLVM tends to call this out of line from the plain loop, which costs a tenth of a default run.
+#[inline(always)]
+pub fn parse_number(data: &[u8]) -> Result<i16, ParseError> {
+    let negative = data.first() == Some(&b'-');
+    let sign = 1 - i16::from(negative) * 2;
+    let mut digits = &data[usize::from(negative)..];
+    // Zero padding like `007.0` doesn't change the value, so trim it down to the arms below.
+    while let [b'0', rest @ ..] = digits
+        && rest.len() > 3
+    {
+        digits = rest;
+    }
+    Ok(match *digits {
+        [ones @ b'0'..=b'9', b'.', decimal @ b'0'..=b'9'] => {
+            let ones = (ones - b'0') as i16;
+            let frac = (decimal - b'0') as i16;
+            (ones * 10 + frac) * sign
+        }
+        [
+            tens @ b'0'..=b'9',
+            ones @ b'0'..=b'9',
+            b'.',
+            decimal @ b'0'..=b'9',
+        ] => {
+            let tens = (tens - b'0') as i16;
+            let ones = (ones - b'0') as i16;
+            let frac = (decimal - b'0') as i16;
+            (tens * 100 + ones * 10 + frac) * sign
+        }
+        _ => {
+            return Err(ParseError::InvalidNumber { bytes: data.into() });
+        }
+    })
+}
+
+/// Parses a decimal with exactly `places` digits after the point, like `-12.34` for two, scaled to
+/// an integer.
+fn parse_fixed(data: &[u8], places: usize) -> Result<i16, ParseError> {
+    let invalid = || ParseError::InvalidNumber { bytes: data.into() };
+    let (negative, unsigned) = match data {
+        [b'-', rest @ ..] => (true, rest),
+        _ => (false, data),
+    };
+    let point = unsigned.len().checked_sub(places + 1).ok_or_else(invalid)?;
+    let (whole, frac) = (&unsigned[..point], &unsigned[point + 1..]);
+    if whole.is_empty() || unsigned[point] != b'.' {
+        return Err(invalid());
+    }
+    let value = whole.iter().chain(frac).try_fold(0i16, |acc, &d| {
+        d.is_ascii_digit()
+            .then(|| acc.checked_mul(10)?.checked_add(i16::from(d - b'0')))
+            .flatten()
+    });
+    value
+        .map(|value| if negative { -value } else { value })
+        .ok_or_else(invalid)
+}
+
+/// Parses any plain decimal, like `12`, `+7` or `12.345`, rounding it half up to tenths.
+fn parse_number_tolerant(data: &[u8]) -> Result<i16, ParseError> {
+    parse_decimal(data)
+        .and_then(|tenths| i16::try_from(tenths).ok())
+        .ok_or_else(|| ParseError::InvalidNumber { bytes: data.into() })
+}
+
+/// Parses a signed integer that is already in tenths, like `-45` for `-4.5`.
+fn parse_integer_tenths(data: &[u8]) -> Result<i16, ParseError> {
+    parse_integer(data)
+        .and_then(|tenths| i16::try_from(tenths).ok())
+        .ok_or_else(|| ParseError::InvalidNumber { bytes: data.into() })
+}
+
+/// Parses an optionally signed run of digits, or `None` if it isn't one or overflows.
+fn parse_integer(data: &[u8]) -> Option<i64> {
+    let (negative, digits) = match data {
+        [b'-', rest @ ..] => (true, rest),
+        [b'+', rest @ ..] => (false, rest),
+        _ => (false, data),
+    };
+    if digits.is_empty() {
+        return None;
+    }
+    let value = digits.iter().try_fold(0i64, |acc, &d| {
+        d.is_ascii_digit()
+            .then(|| acc.checked_mul(10)?.checked_add(i64::from(d - b'0')))
+            .flatten()
+    })?;
+    Some(if negative { -value } else { value })
+}
+
+/// Parses a plain decimal in tenths, rounded half up, or `None` if it isn't one or overflows.
+fn parse_decimal(data: &[u8]) -> Option<i32> {
+    let (negative, unsigned) = match data {
+        [b'-', rest @ ..] => (true, rest),
+        [b'+', rest @ ..] => (false, rest),
+        _ => (false, data),
+    };
+    let (whole, frac) = match memchr(b'.', unsigned) {
+        Some(idx) => (&unsigned[..idx], &unsigned[idx + 1..]),
+        None => (unsigned, &[][..]),
+    };
+    if whole.is_empty() && frac.is_empty() || !whole.iter().chain(frac).all(u8::is_ascii_digit) {
+        return None;
+    }
+    let ones = whole.iter().try_fold(0i32, |acc, d| {
+        acc.checked_mul(10)?.checked_add(i32::from(d - b'0'))
+    })?;
+    let tenths = ones
+        .checked_mul(10)?
+        .checked_add(frac.first().map_or(0, |d| i32::from(d - b'0')))?;
+    // Half up means towards positive infinity, so an exact half only rounds away from zero for
+    // positive values.
+    let rest = frac.get(1..).unwrap_or_default();
+    let round_away = match rest.split_first() {
+        Some((&half, rest)) if negative => {
+            half > b'5' || half == b'5' && rest.iter().any(|&d| d != b'0')
+        }
+        Some((&half, _)) => half >= b'5',
+        None => false,
+    };
+    let tenths = tenths.checked_add(i32::from(round_away))?;
+    Some(if negative { -tenths } else { tenths })
+}
+
+/// Aggregates of the values seen for one station, kept exactly as fixed-point tenths with an `i64`
+/// sum. Only [`Stat::mean`] and display round anything.
+#[derive(Clone, Copy)]
+pub struct Stat {
+    min: i16,
+    max: i16,
+    total: i64,
+    count: u32,
+    first: i16,
+    last: i16,
+}
+impl Stat {
+    #[inline]
+    pub fn new(num: i16) -> Self {
+        Self {
+            min: num,
+            max: num,
+            total: num.into(),
+            count: 1,
+            first: num,
+            last: num,
+        }
+    }
+    #[inline]
+    pub fn update(&mut self, num: i16) {
+        *self = self.observe(num);
+    }
+    /// [`Stat::update`] without keeping the last value, which is then meaningless, for runs that
+    /// never report it. An empty stat still starts as [`Stat::new`], so every value it holds is
+    /// one that was observed.
+    #[inline]
+    pub(crate) fn update_unordered(&mut self, num: i16) {
+        if self.is_empty() {
+            *self = Self::new(num);
+            return;
+        }
+        self.min = self.min.min(num);
+        self.max = self.max.max(num);
+        self.total += i64::from(num);
+        self.count += 1;
+    }
+    /// Returns this stat with `num` observed, so values can be folded as in
+    /// `values.fold(Stat::default(), Stat::observe)`.
+    #[inline]
+    pub fn observe(mut self, num: i16) -> Self {
+        self.min = self.min.min(num);
+        self.max = self.max.max(num);
+        self.total += i64::from(num);
+        if self.is_empty() {
+            self.first = num;
+        }
+        self.last = num;
+        self.count += 1;
+        self
+    }
+    /// Folds in `other`, which must hold values observed after this stat's for the first and last
+    /// values to stay right. Everything else comes out the same in any order.
+    #[inline]
+    pub fn merge(&mut self, other: &Self) {
+        if other.is_empty() {
+            return;
+        }
+        if self.is_empty() {
+            self.first = other.first;
+        }
+        self.last = other.last;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.total += other.total;
+        self.count += other.count;
+    }
+    /// Folds `stats` into a single stat, or `None` if there are none.
+    pub fn merge_all(stats: impl IntoIterator<Item = Self>) -> Option<Self> {
+        stats.into_iter().reduce(|mut acc, stat| {
+            acc.merge(&stat);
+            acc
+        })
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+    /// The smallest value seen, exactly as parsed, in tenths of a degree. Meaningless for an empty
+    /// stat.
+    pub fn min(&self) -> i16 {
+        self.min
+    }
+    /// The largest value seen, in the same units as [`Stat::min`].
+    pub fn max(&self) -> i16 {
+        self.max
+    }
+    /// The exact sum of every value seen, in the same units as [`Stat::min`].
+    pub fn total(&self) -> i64 {
+        self.total
+    }
+    /// How many values were seen.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+    /// Mean with `places` decimal places, scaled to an integer, or `None` if nothing has been
+    /// observed. Values have `scale` decimal places, and one place gives tenths.
+    ///
+    /// Computed exactly in integers and rounded half up, matching the reference implementation's
+    /// `Math.round`, so large counts can't lose precision the way a float division would.
+    pub fn mean(&self, scale: u32, places: u32) -> Option<i64> {
+        (!self.is_empty()).then(|| rescale(self.total, self.count.into(), scale, places))
+    }
+    /// Min, mean and max with the decimal places `precision` gives each, or `None` if nothing has
+    /// been observed.
+    fn columns(&self, precision: Precision) -> Option<[Fixed; 3]> {
+        let fixed = |value: i16, places| Fixed {
+            value: rescale(value.into(), 1, precision.scale, places),
+            places,
+        };
+        let mean = Fixed {
+            value: self.mean(precision.scale, precision.mean)?,
+            places: precision.mean,
+        };
+        Some([
+            fixed(self.min, precision.min),
+            mean,
+            fixed(self.max, precision.max),
+        ])
+    }
+    /// Index of the `width`-wide band holding the mean as displayed with `precision`, or `None` if
+    /// nothing has been observed. `width` is in the same units as [`Stat::min`]. Band 0 starts at
+    /// zero and negative means fall in negative bands.
+    ///
+    /// Bucketing the rounded mean rather than the exact one means a mean shown on a band boundary
+    /// always lands in the band that starts there.
+    pub fn mean_bucket(&self, width: NonZero<u16>, precision: Precision) -> Option<i64> {
+        let mean = self.mean(precision.scale, precision.mean)?;
+        // The mean has `precision.mean` decimal places and `width` has `precision.scale`.
+        let numer = i128::from(mean) * 10i128.pow(precision.scale);
+        let denom = i128::from(width.get()) * 10i128.pow(precision.mean);
+        Some(numer.div_euclid(denom) as i64)
+    }
+    /// The first and last values seen, with as many decimal places as `precision` gives the min,
+    /// or `None` if nothing has been observed.
+    fn ends(&self, precision: Precision) -> Option<[Fixed; 2]> {
+        let fixed = |value: i16| Fixed {
+            value: rescale(value.into(), 1, precision.scale, precision.min),
+            places: precision.min,
+        };
+        (!self.is_empty()).then(|| [fixed(self.first), fixed(self.last)])
+    }
+    /// This stat with its values moved from `from` to `to` decimal places, which must be at least
+    /// as many, or `None` if they no longer fit. Nothing is rounded.
+    fn rescaled(&self, from: u32, to: u32) -> Option<Self> {
+        let factor = 10i16.checked_pow(to.checked_sub(from)?)?;
+        if self.is_empty() {
+            return Some(*self);
+        }
+        Some(Self {
+            min: self.min.checked_mul(factor)?,
+            max: self.max.checked_mul(factor)?,
+            total: self.total.checked_mul(factor.into())?,
+            count: self.count,
+            first: self.first.checked_mul(factor)?,
+            last: self.last.checked_mul(factor)?,
+        })
+    }
+    /// Displays min, mean and max with the decimal places `precision` gives each.
+    pub fn display(&self, precision: Precision) -> impl Display {
+        StatDisplay {
+            stat: *self,
+            precision,
+        }
+    }
+}
+/// A stat with no observations, which is the identity for [`Stat::merge`].
+impl Default for Stat {
+    fn default() -> Self {
+        Self {
+            min: i16::MAX,
+            max: i16::MIN,
+            total: 0,
+            count: 0,
+            first: i16::MAX,
+            last: i16::MIN,
+        }
+    }
+}
+struct StatDisplay {
+    stat: Stat,
+    precision: Precision,
+}
+impl Display for StatDisplay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.stat.columns(self.precision) {
+            Some([min, mean, max]) => write!(f, "{min}/{mean}/{max}"),
+            None => f.write_str("-/-/-"),
+        }
+    }
+}
+
+/// `total / count`, where `total` has `scale` decimal places, with `places` decimal places instead,
+/// scaled to an integer and rounded half up.
+fn rescale(total: i64, count: i64, scale: u32, places: u32) -> i64 {
+    let numer = i128::from(total) * 10i128.pow(places);
+    let denom = 10i128.pow(scale) * i128::from(count);
+    (2 * numer + denom).div_euclid(2 * denom) as i64
+}
+
+/// A fixed-point value displayed with `places` decimal places.
+struct Fixed {
+    value: i64,
+    places: u32,
+}
+impl Fixed {
+    fn to_f64(&self) -> f64 {
+        self.value as f64 / 10f64.powi(self.places as i32)
+    }
+}
+impl Display for Fixed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.value < 0 { "-" } else { "" };
+        let abs = self.value.unsigned_abs();
+        if self.places == 0 {
+            return write!(f, "{sign}{abs}");
+        }
+        let unit = 10u64.pow(self.places);
+        let width = self.places as usize;
+        write!(f, "{sign}{}.{:0width$}", abs / unit, abs % unit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs,
+        hash::{BuildHasher, RandomState},
+        io::Write,
+        process,
+        sync::atomic::AtomicUsize,
+    };
+
+    use gxhash::{GxBuildHasher, GxHasher};
+
+    use super::*;
+
+    const MEASUREMENTS: &[u8] = b"Hamburg;12.0\nBulawayo;8.9\nPalembang;38.8\nHamburg;34.2\n\
+        St. John's;15.2\nCracow;12.6\nBulawayo;-3.1\nPalembang;-0.4\nHamburg;-7.5\n";
+
+    /// A file in the temp directory, removed again when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        /// A path no other test uses, with nothing written to it yet.
+        fn new(name: &str) -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let id = NEXT.fetch_add(1, Ordering::Relaxed);
+            Self(env::temp_dir().join(format!("brc-test-{}-{id}-{name}", process::id())))
+        }
+
+        fn with(name: &str, contents: &[u8]) -> Self {
+            let file = Self::new(name);
+            fs::write(&file.0, contents).unwrap();
+            file
+        }
+
+        fn arg(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// Runs `brc` quietly with `args`.
+    fn run_quiet(args: &[&str]) -> Result<()> {
+        run(iter::once("--quiet")
+            .chain(args.iter().copied())
+            .map(String::from))
+    }
+
+    /// Runs `brc` with `args`, returning the report it writes.
+    fn report_of(args: &[&str]) -> String {
+        let output = TempFile::new("report");
+        run_quiet(&[&["--output", output.arg()], args].concat()).unwrap();
+        fs::read_to_string(&output.0).unwrap()
+    }
+
+    #[test]
+    fn empty_stat_displays_dashes() {
+        let stat = Stat::default();
+        assert!(stat.is_empty());
+        assert_eq!(stat.mean(1, 1), None);
+        assert_eq!(stat.display(Precision::default()).to_string(), "-/-/-");
+    }
+
+    #[test]
+    fn leading_zeros_keep_the_value() {
+        assert_eq!(parse_number(b"00.0").ok(), Some(0));
+        assert_eq!(parse_number(b"007.0").ok(), Some(70));
+        assert_eq!(parse_number(b"-00.5").ok(), Some(-5));
+        assert_eq!(parse_number(b"-12.3").ok(), Some(-123));
+        assert_eq!(parse_number(b"0099.9").ok(), Some(999));
+    }
+
+    #[test]
+    fn tolerant_numbers_round_to_tenths() {
+        let tolerant = Config {
+            tolerant: true,
+            ..Config::default()
+        };
+        for (input, tenths) in [
+            (&b"12"[..], 120),
+            (b"12.345", 123),
+            (b"+7", 70),
+            (b"-0", 0),
+            (b"100.5", 1005),
+            (b"12.35", 124),
+            (b"-12.35", -123),
+            (b"-12.351", -124),
+        ] {
+            assert_eq!(
+                parse_reading(input, &tolerant).ok(),
+                Some(Some(tenths)),
+                "{}",
+                input.escape_ascii()
+            );
+        }
+        for input in [&b""[..], b".", b"1.2.3", b"12a", b"4000"] {
+            assert!(
+                parse_reading(input, &tolerant).is_err(),
+                "{}",
+                input.escape_ascii()
+            );
+        }
+        assert!(parse_reading(b"12.345", &Config::default()).is_err());
+        assert!(parse_reading(b"12", &Config::default()).is_err());
+    }
+
+    #[test]
+    fn units_are_stripped_and_converted() {
+        assert_eq!(split_unit(b"12.3C"), (&b"12.3"[..], &b"C"[..]));
+        assert_eq!(split_unit(b"-4.0degF"), (&b"-4.0"[..], &b"degF"[..]));
+        assert_eq!(split_unit(b"12.3"), (&b"12.3"[..], &b""[..]));
+        let strip = Config {
+            strip_unit: true,
+            ..Config::default()
+        };
+        assert_eq!(parse_reading(b"12.3C", &strip).ok(), Some(Some(123)));
+        assert_eq!(parse_reading(b"50.0F", &strip).ok(), Some(Some(500)));
+        let convert = Config {
+            strip_unit: true,
+            fahrenheit: true,
+            ..Config::default()
+        };
+        assert_eq!(parse_reading(b"50.0F", &convert).ok(), Some(Some(100)));
+        assert_eq!(parse_reading(b"-4.0F", &convert).ok(), Some(Some(-200)));
+        assert_eq!(parse_reading(b"12.3C", &convert).ok(), Some(Some(123)));
+        assert_eq!(fahrenheit_to_celsius(986), 370);
+        assert!(parse_reading(b"12.3C", &Config::default()).is_err());
+    }
+
+    /// A stat built from its fields, to reach counts too large to observe one value at a time.
+    fn stat_with(min: i16, max: i16, total: i64, count: u32) -> Stat {
+        Stat {
+            min,
+            max,
+            total,
+            count,
+            first: min,
+            last: max,
+        }
+    }
+
+    #[test]
+    fn means_round_half_up() {
+        assert_eq!(rescale(25, 2, 1, 1), 13);
+        assert_eq!(rescale(-25, 2, 1, 1), -12);
+        assert_eq!(rescale(-35, 2, 1, 1), -17);
+        let stat = [-12, -13].into_iter().fold(Stat::default(), Stat::observe);
+        assert_eq!(
+            stat.display(Precision::default()).to_string(),
+            "-1.3/-1.2/-1.2"
+        );
+    }
+
+    #[test]
+    fn means_just_below_zero_display_as_zero() {
+        let stat = [-1, 0].into_iter().fold(Stat::default(), Stat::observe);
+        assert_eq!(stat.mean(1, 1), Some(0));
+        assert_eq!(
+            stat.display(Precision::default()).to_string(),
+            "-0.1/0.0/0.0"
+        );
+    }
+
+    #[test]
+    fn means_stay_exact_for_large_counts() {
+        // An `f32` division can't tell these two means apart, so would round both the same way.
+        let count = 4_000_000_000;
+        let half = stat_with(0, 999, 123 * i64::from(count) + i64::from(count / 2), count);
+        assert_eq!(half.mean(1, 1), Some(124));
+        let below = stat_with(
+            0,
+            999,
+            123 * i64::from(count) + i64::from(count / 2) - 1,
+            count,
+        );
+        assert_eq!(below.mean(1, 1), Some(123));
+        assert_eq!(below.mean(1, 3), Some(12_350));
+    }
+
+    #[test]
+    fn combining_ranges_matches_one_run() {
+        let input = TempFile::with("input", MEASUREMENTS);
+        // Split on a line boundary, as `--emit-plan` would.
+        let split = memchr_iter(b'\n', MEASUREMENTS).nth(3).unwrap() + 1;
+        let (first, second) = (TempFile::new("first"), TempFile::new("second"));
+        for (range, snapshot) in [
+            (format!("0,{split}"), &first),
+            (format!("{split},{}", MEASUREMENTS.len() - split), &second),
+        ] {
+            run_quiet(&[
+                "--range",
+                &range,
+                "--save-partial",
+                snapshot.arg(),
+                input.arg(),
+            ])
+            .unwrap();
+        }
+        let single = report_of(&[input.arg()]);
+        assert!(single.contains("Hamburg=-7.5/12.9/34.2"), "{single}");
+        assert_eq!(report_of(&["--combine", first.arg(), second.arg()]), single);
+    }
+
+    #[test]
+    fn merging_into_a_snapshot_matches_one_run() {
+        let split = memchr_iter(b'\n', MEASUREMENTS).nth(4).unwrap() + 1;
+        let yesterday = TempFile::with("yesterday", &MEASUREMENTS[..split]);
+        let today = TempFile::with("today", &MEASUREMENTS[split..]);
+        let rolling = TempFile::new("rolling");
+        report_of(&["--merge-into", rolling.arg(), yesterday.arg()]);
+        assert_eq!(
+            report_of(&["--merge-into", rolling.arg(), today.arg()]),
+            report_of(&[yesterday.arg(), today.arg()])
+        );
+    }
+
+    #[test]
+    fn snapshots_at_different_scales() {
+        let tenths = TempFile::with("tenths", b"A;1.0\nB;-0.5\n");
+        let hundredths = TempFile::with("hundredths", b"A;2.25\nB;-0.75\n");
+        let (coarse, fine) = (TempFile::new("coarse"), TempFile::new("fine"));
+        run_quiet(&["--save-partial", coarse.arg(), tenths.arg()]).unwrap();
+        run_quiet(&[
+            "--auto-scale",
+            "--save-partial",
+            fine.arg(),
+            hundredths.arg(),
+        ])
+        .unwrap();
+        // Combining rescales everything to hundredths before merging.
+        assert_eq!(
+            report_of(&["--combine", "--precision", "2", coarse.arg(), fine.arg()]),
+            "Num stations: 2\n{A=1.00/1.63/2.25, B=-0.75/-0.62/-0.50}\n"
+        );
+        // A run in tenths can't take in hundredths without rounding them.
+        let err = run_quiet(&["--merge-into", fine.arg(), tenths.arg()]).unwrap_err();
+        assert!(err.to_string().contains("2 decimal places"), "{err}");
+    }
+
+    #[test]
+    fn sorted_input_matches_hashing() {
+        // Runs of 7 lines, so most chunk boundaries fall inside a station's run.
+        let mut data = Vec::new();
+        for (station, offset) in [("Abha", 0), ("Accra", 13), ("Bulawayo", -41), ("Zürich", 7)] {
+            for i in 0..7 {
+                writeln!(data, "{station};{}.{}", (i * 3 + offset) / 10, (i * 7) % 10).unwrap();
+            }
+        }
+        let input = TempFile::with("sorted", &data);
+        let expected = report_of(&[input.arg()]);
+        for chunks in ["1", "3", "5", "11"] {
+            let sorted = report_of(&["--sorted-input", "--chunks", chunks, input.arg()]);
+            assert_eq!(sorted, expected, "--chunks {chunks}");
+        }
+    }
+
+    #[test]
+    fn first_and_last_follow_file_order_across_chunks() {
+        let input = TempFile::with("ordered", MEASUREMENTS);
+        let one = report_of(&["--first-last", "--chunks", "1", input.arg()]);
+        assert!(one.contains("Hamburg=-7.5/12.9/34.2/12.0/-7.5"), "{one}");
+        assert!(one.contains("Palembang=-0.4/19.2/38.8/38.8/-0.4"), "{one}");
+        assert_eq!(
+            report_of(&["--first-last", "--chunks", "7", input.arg()]),
+            one
+        );
+    }
+
+    #[test]
+    fn rescaling_fails_when_values_no_longer_fit() {
+        let stations = HashMap::from_iter([(&b"A"[..], Stat::new(400))]);
+        let rescaled = rescale_stations(stations.clone(), 1, 2).unwrap();
+        assert_eq!(rescaled[&b"A"[..]].total(), 4000);
+        assert!(rescale_stations(stations, 1, 3).is_none());
+    }
+
+    /// Formats the report for `data` under the command line `args`, without touching any files.
+    fn report_with(data: &[u8], args: &[&str]) -> String {
+        let args = iter::once("--quiet").chain(args.iter().copied());
+        report(data, &Config::from_args(args.map(String::from)).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn plain_lines_match_the_general_path() {
+        let data =
+            b"Hamburg;12.0\n\nBulawayo;8.9\nbroken\nHamburg;007.5\nCracow;1.23\nCracow;-0.4\n";
+        // A name length cap that no station reaches still takes every line through the options.
+        let general = ["--max-name-len", "100"];
+        for args in [&[][..], &["--chunks", "3"], &["--invalid-as", "0.0"]] {
+            let args = iter::once("--skip-invalid").chain(args.iter().copied());
+            let plain = args.clone().collect::<Vec<_>>();
+            let options = args.chain(general).collect::<Vec<_>>();
+            assert!(
+                Config::from_args(plain.iter().map(|arg| arg.to_string()))
+                    .unwrap()
+                    .plain_lines()
+                    .is_some()
+            );
+            assert_eq!(report_with(data, &plain), report_with(data, &options));
+        }
+        for config in [
+            Config::default(),
+            Config::from_args(general.map(String::from)).unwrap(),
+        ] {
+            let config = Config {
+                on_malformed: MalformedPolicy::Skip,
+                ..config
+            };
+            let result = aggregate(&[data], NonZero::<usize>::MIN, &config).unwrap();
+            assert_eq!((result.total_lines, result.malformed_lines), (6, 2));
+        }
+    }
+
+    /// gxhash under a fixed seed, where the default picks a random one for every map.
+    #[derive(Default)]
+    struct SeededGx<const SEED: i64>;
+
+    impl<const SEED: i64> BuildHasher for SeededGx<SEED> {
+        type Hasher = GxHasher;
+
+        fn build_hasher(&self) -> GxHasher {
+            GxHasher::with_seed(SEED)
+        }
+    }
+
+    /// The report of `data` split into `parts` chunks whose stations are hashed with `S`.
+    fn report_hashed<S: BuildHasher + Default + Send>(
+        data: &[u8],
+        parts: usize,
+        config: &Config,
+    ) -> String {
+        let parts = NonZero::new(parts).unwrap();
+        let scanned =
+            scan::<StatVisitor<S>>(&[data], parts, config, &RunState::new(config)).unwrap();
+        let stations = scanned.visitor.stations.into_iter().collect();
+        let mut report = Vec::new();
+        write_report(&mut report, &stations, config).unwrap();
+        String::from_utf8(report).unwrap()
+    }
+
+    #[test]
+    fn reports_dont_depend_on_chunking_or_hash_seeds() {
+        let mut rng = SplitMix64::new(7);
+        let mut data = Vec::new();
+        for _ in 0..5_000 {
+            let station = rng.next_u64() % 300;
+            let value = Fixed {
+                value: (rng.next_u64() % 1999) as i64 - 999,
+                places: 1,
+            };
+            writeln!(data, "Station {station};{value}").unwrap();
+        }
+        for args in [&["--quiet"][..], &["--quiet", "--first-last"]] {
+            let config = Config::from_args(args.iter().map(|arg| arg.to_string())).unwrap();
+            let expected = report_hashed::<GxBuildHasher>(&data, 1, &config);
+            for parts in [1, 2, 7, 64] {
+                for report in [
+                    report_hashed::<GxBuildHasher>(&data, parts, &config),
+                    report_hashed::<SeededGx<1>>(&data, parts, &config),
+                    report_hashed::<SeededGx<-42>>(&data, parts, &config),
+                    report_hashed::<RandomState>(&data, parts, &config),
+                ] {
+                    assert_eq!(report, expected, "{args:?} in {parts} chunks");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn seeded_samples_dont_depend_on_chunking() {
+        let mut data = Vec::new();
+        for station in 0..2_000 {
+            writeln!(data, "Station {station};1.0").unwrap();
+        }
+        let sample = ["--sample-rate", "0.5", "--seed", "7"];
+        let expected = report_with(&data, &sample);
+        let kept = expected.matches("Station").count();
+        assert!((800..1_200).contains(&kept), "{kept} kept");
+        for chunks in ["2", "7", "64"] {
+            let report = report_with(&data, &[&sample[..], &["--chunks", chunks]].concat());
+            assert_eq!(report, expected, "{chunks} chunks");
+        }
+        let input = TempFile::with("sampled-stream", &data);
+        for size in ["100", "4096"] {
+            let args = [&sample[..], &["--input-buffer-size", size]].concat();
+            assert_eq!(
+                report_streamed(&input, &args).1,
+                expected,
+                "blocks of {size}"
+            );
+        }
+        let reseeded = report_with(&data, &["--sample-rate", "0.5", "--seed", "8"]);
+        assert_ne!(reseeded, expected);
+    }
+
+    #[test]
+    fn line_counts_add_up_past_u32() {
+        let chunk = |lines| Scanned {
+            lines,
+            malformed_lines: lines,
+            visitor: StatVisitor::<GxBuildHasher>::default(),
+        };
+        let mut scanned = chunk(u64::from(u32::MAX));
+        scanned.merge(chunk(u64::from(u32::MAX)));
+        scanned.merge(chunk(2));
+        assert_eq!(scanned.lines, 2 * u64::from(u32::MAX) + 2);
+        assert_eq!(scanned.malformed_lines, scanned.lines);
+
+        let mut owned = OwnedAggregateResult {
+            stations: HashMap::new(),
+            total_lines: 0,
+            malformed_lines: 0,
+        };
+        for total_lines in [u64::from(u32::MAX), 1] {
+            owned.absorb(AggregateResult {
+                stations: HashMap::new(),
+                total_lines,
+                malformed_lines: total_lines,
+            });
+        }
+        assert_eq!(owned.total_lines, 1 << 32);
+        assert_eq!(owned.malformed_lines, 1 << 32);
+    }
+
+    #[test]
+    fn nul_delimited_records() {
+        let nul = MEASUREMENTS
+            .iter()
+            .map(|&byte| if byte == b'\n' { 0 } else { byte })
+            .collect::<Vec<_>>();
+        let expected = report_with(MEASUREMENTS, &[]);
+        for chunks in ["1", "4"] {
+            let args = ["--record-delim", "\\0", "--chunks", chunks];
+            assert_eq!(report_with(&nul, &args), expected, "--chunks {chunks}");
+        }
+        // A newline is just part of the name once records end in NUL.
+        let report = report_with(b"A\nB;1.0\0", &["--record-delim", "\\0"]);
+        assert!(report.contains("{A\nB=1.0/1.0/1.0}"), "{report}");
+    }
+
+    #[test]
+    fn nul_delimited_streams() {
+        let mut nul = MEASUREMENTS
+            .iter()
+            .map(|&byte| if byte == b'\n' { 0 } else { byte })
+            .collect::<Vec<_>>();
+        nul.extend_from_slice(b"New\nline;1.0\0");
+        let input = TempFile::with("nul-stream", &nul);
+        let mapped = report_of(&["--record-delim", "\\0", input.arg()]);
+        // Blocks this small cut most records in two, and a block of 1 ends after every NUL.
+        for size in ["1", "5", "13", "40", "4096"] {
+            let (lines, streamed) = report_streamed(
+                &input,
+                &["--record-delim", "\\0", "--input-buffer-size", size],
+            );
+            assert_eq!(lines, 10, "blocks of {size}");
+            assert_eq!(streamed, mapped, "blocks of {size}");
+        }
+    }
+
+    #[test]
+    fn fixed_width_records() {
+        let mut fixed = Vec::new();
+        for line in MEASUREMENTS.split_inclusive(|&byte| byte == b'\n') {
+            let line = str::from_utf8(line).unwrap().trim_end();
+            let (name, value) = line.split_once(';').unwrap();
+            writeln!(fixed, "{name:<10}{value:>5}").unwrap();
+        }
+        assert_eq!(
+            report_with(&fixed, &["--fixed-width", "10,5"]),
+            report_with(MEASUREMENTS, &[])
+        );
+        let config = Config::from_args(["--quiet", "--fixed-width", "10,5"].map(String::from));
+        assert!(report(b"Hamburg   12\n", &config.unwrap()).is_err());
+    }
+
+    #[test]
+    fn groups_hierarchical_names_by_prefix() {
+        let input = TempFile::with(
+            "hierarchy",
+            b"EU/DE/Berlin;10.0\nEU/DE/Hamburg;20.0\nEU/FR/Paris;30.0\nAS/JP/Tokyo;-5.0\nAS;1.0\n",
+        );
+        assert_eq!(
+            report_of(&["--group-by-prefix", "/,1", input.arg()]),
+            "Num stations: 2\n{AS=-5.0/-2.0/1.0, EU=10.0/20.0/30.0}\n"
+        );
+        assert_eq!(
+            report_of(&["--group-by-prefix", "/,2", input.arg()]),
+            "Num stations: 4\n{AS=1.0/1.0/1.0, AS/JP=-5.0/-5.0/-5.0, EU/DE=10.0/15.0/20.0, \
+             EU/FR=30.0/30.0/30.0}\n"
+        );
+    }
+
+    #[test]
+    fn merge_all_matches_one_pass() {
+        let values = [120, -75, 342, 0, -999, 999];
+        let one_pass = values.into_iter().fold(Stat::default(), Stat::observe);
+        let merged = Stat::merge_all(values.map(Stat::new)).unwrap();
+        assert_eq!(
+            (merged.min(), merged.max(), merged.total(), merged.count()),
+            (
+                one_pass.min(),
+                one_pass.max(),
+                one_pass.total(),
+                one_pass.count()
+            )
+        );
+        assert_eq!((merged.first, merged.last), (120, 999));
+        assert!(Stat::merge_all([]).is_none());
+    }
+
+    #[test]
+    fn blank_lines_are_skipped_by_every_path() {
+        let data = b"A;1.0\n\nB;2.0\nC;3.0\n\nD;4.0\n";
+        let input = TempFile::with("blank-lines", data);
+        let mapped = report_of(&["--chunks", "1", input.arg()]);
+        assert!(mapped.starts_with("Num stations: 4\n"), "{mapped}");
+        for chunks in ["2", "3", "5"] {
+            assert_eq!(report_of(&["--chunks", chunks, input.arg()]), mapped);
+        }
+        for size in ["1", "6", "7", "13"] {
+            let (lines, streamed) = report_streamed(&input, &["--input-buffer-size", size]);
+            assert_eq!(lines, 4);
+            assert_eq!(streamed, mapped, "blocks of {size}");
+        }
+    }
+
+    /// Aggregates `input` as a stream, with `args`, into its line count and report. Streams are
+    /// read the same way whether they come from stdin or a file, so a file read in small blocks
+    /// stands in for a pipe.
+    fn report_streamed(input: &TempFile, args: &[&str]) -> (u64, String) {
+        let args = iter::once("--quiet").chain(args.iter().copied());
+        let config = Config::from_args(args.map(String::from)).unwrap();
+        let mut owned = OwnedAggregateResult {
+            stations: HashMap::new(),
+            total_lines: 0,
+            malformed_lines: 0,
+        };
+        aggregate_stream(
+            &input.0,
+            &mut owned,
+            NonZero::new(3).unwrap(),
+            &config,
+            &RunState::new(&config),
+        )
+        .unwrap();
+        let mut report = Vec::new();
+        write_report(&mut report, &owned.borrow().stations, &config).unwrap();
+        (owned.total_lines, String::from_utf8(report).unwrap())
+    }
+
+    #[test]
+    fn multi_byte_separator_and_delimiter() {
+        let crlf = Delimiter::new(b"\r\n");
+        let data = b"Hamburg::12.0\r\nBulawayo::8.9\r\nHamburg::-3.4\r\n";
+        assert_eq!(
+            records(data, &crlf).collect::<Vec<_>>(),
+            [&b"Hamburg::12.0"[..], b"Bulawayo::8.9", b"Hamburg::-3.4"]
+        );
+        assert_eq!(Delimiter::new(b"::").find(b"St. John's::15.2"), Some(10));
+        for parts in 1..=data.len() {
+            let chunks = chunk_data(data, NonZero::new(parts).unwrap(), &crlf);
+            assert_eq!(chunks.concat(), data);
+            assert!(
+                chunks.iter().all(|chunk| chunk.ends_with(b"\r\n")),
+                "{parts} parts"
+            );
+        }
+        let args = [
+            "--separator",
+            "::",
+            "--record-delim",
+            "\\r\\n",
+            "--chunks",
+            "3",
+        ];
+        assert_eq!(
+            report_with(data, &args),
+            report_with(b"Hamburg;12.0\nBulawayo;8.9\nHamburg;-3.4\n", &[])
+        );
+    }
+
+    #[test]
+    fn lines_without_a_separator_can_be_skipped() {
+        let data = b"Hamburg;12.0\n# exported by station logger\nBulawayo;8.9\n";
+        let Err(err) = aggregate(&[data], NonZero::<usize>::MIN, &Config::default()) else {
+            panic!("a line without a separator was accepted");
+        };
+        assert!(
+            err.chain()
+                .any(|cause| matches!(cause.downcast_ref(), Some(ParseError::MissingSeparator))),
+            "{err:#}"
+        );
+        let skip = Config {
+            on_malformed: MalformedPolicy::Skip,
+            ..Config::default()
+        };
+        let result = aggregate(&[data], NonZero::<usize>::MIN, &skip).unwrap();
+        assert_eq!((result.total_lines, result.malformed_lines), (3, 1));
+        assert_eq!(
+            report_with(data, &["--skip-invalid"]),
+            report_with(b"Hamburg;12.0\nBulawayo;8.9\n", &[])
+        );
+    }
+
+    #[test]
+    fn summary_follows_the_text_report_only() {
+        assert_eq!(
+            report_with(b"A;1.0\nB;-3.0\nA;5.0\n", &["--summary"]),
+            "Num stations: 2\n{A=1.0/3.0/5.0, B=-3.0/-3.0/-3.0}\nSummary: -3.0/1.0/5.0\n"
+        );
+        for args in [
+            &["--format", "ndjson"][..],
+            &["--format", "tsv"],
+            &["--stations-only"],
+        ] {
+            let args = ["--summary"].iter().chain(args).map(|arg| arg.to_string());
+            assert!(Config::from_args(args).is_err());
+        }
+    }
+
+    #[test]
+    fn excluded_stations_are_left_out() {
+        let report = report_with(MEASUREMENTS, &["--exclude", "Hamburg,Cracow"]);
+        assert!(
+            !report.contains("Hamburg") && !report.contains("Cracow"),
+            "{report}"
+        );
+        assert!(report.starts_with("Num stations: 3\n"), "{report}");
+        assert_eq!(
+            report_with(
+                MEASUREMENTS,
+                &["--only", "Hamburg,Bulawayo", "--exclude", "Hamburg"]
+            ),
+            "Num stations: 1\n{Bulawayo=-3.1/2.9/8.9}\n"
+        );
+    }
+
+    #[test]
+    fn value_first_records() {
+        assert_eq!(
+            report_with(b"12.3;Berlin\n-4.0;Berlin\n", &["--value-first"]),
+            report_with(b"Berlin;12.3\nBerlin;-4.0\n", &[])
+        );
+    }
+
+    #[test]
+    fn observe_matches_update() {
+        let values = [120, -75, 342, 0, 342];
+        let folded = values.into_iter().fold(Stat::default(), Stat::observe);
+        let mut updated = Stat::default();
+        for value in values {
+            updated.update(value);
+        }
+        assert_eq!(
+            (folded.min(), folded.max(), folded.total(), folded.count()),
+            (
+                updated.min(),
+                updated.max(),
+                updated.total(),
+                updated.count()
+            )
+        );
+        assert_eq!((folded.first, folded.last), (updated.first, updated.last));
+    }
+
+    #[test]
+    fn sentinels_are_ignored() {
+        let data = b"A;1.0\nA;-999.0\nA;3.0\nA;-99.9\nB;-9999.0\n";
+        let report = report_with(data, &["--ignore-value", "-999.0,-9999.0,-99.9"]);
+        assert_eq!(report, "Num stations: 1\n{A=1.0/2.0/3.0}\n");
+    }
+
+    #[test]
+    fn header_lines_are_skipped() {
+        let first = TempFile::with(
+            "first",
+            b"station;temperature\nHamburg;12.0\nBulawayo;8.9\n",
+        );
+        let second = TempFile::with("second", b"station;temperature\nHamburg;-3.4\n");
+        let expected = report_with(b"Hamburg;12.0\nBulawayo;8.9\nHamburg;-3.4\n", &[]);
+        for chunks in ["1", "3"] {
+            let args = ["--header", "--chunks", chunks, first.arg(), second.arg()];
+            assert_eq!(report_of(&args), expected, "--chunks {chunks}");
+        }
+        assert!(run_quiet(&[first.arg()]).is_err());
+    }
+
+    #[test]
+    fn count_only_matches_a_full_run() {
+        let config = Config::default();
+        let parts = NonZero::new(3).unwrap();
+        let (lines, stations) = count_inputs(&[MEASUREMENTS], parts, &config).unwrap();
+        let full = aggregate(&[MEASUREMENTS], parts, &config).unwrap();
+        assert_eq!(lines, full.total_lines);
+        assert_eq!(stations.len(), full.stations.len());
+        assert_eq!((lines, stations.len()), (9, 5));
+    }
+
+    #[test]
+    fn value_column_of_multi_field_records() {
+        let data =
+            b"Hamburg;2024-01-01;12.0;80\nBulawayo;2024-01-01;8.9;41\nHamburg;2024-01-02;-3.4;77\n";
+        assert_eq!(
+            report_with(data, &["--value-column", "1"]),
+            report_with(b"Hamburg;12.0\nBulawayo;8.9\nHamburg;-3.4\n", &[])
+        );
+    }
+
+    #[test]
+    fn name_column_anywhere_in_the_record() {
+        let data = b"12.0;2024-01-01;Hamburg\n8.9;2024-01-01;Bulawayo\n-3.4;2024-01-02;Hamburg\n";
+        assert_eq!(
+            report_with(data, &["--name-column", "2", "--value-column", "0"]),
+            report_with(b"Hamburg;12.0\nBulawayo;8.9\nHamburg;-3.4\n", &[])
+        );
+    }
+
+    #[test]
+    fn mapped_output_matches_buffered_output() {
+        let input = TempFile::with("mapped-output-input", MEASUREMENTS);
+        for format in ["text", "ndjson", "table"] {
+            let buffered = report_of(&["--format", format, input.arg()]);
+            // A longer file already at the path has to be cut down to the report.
+            let output = TempFile::with("mapped-output", &[b'x'; 4096]);
+            let args = [
+                "--mmap-output",
+                "--output",
+                output.arg(),
+                "--format",
+                format,
+            ];
+            run_quiet(&[&args[..], &[input.arg()]].concat()).unwrap();
+            assert_eq!(fs::read_to_string(&output.0).unwrap(), buffered, "{format}");
+        }
+    }
+
+    #[test]
+    fn verify_finds_the_first_differing_station() {
+        let input = TempFile::with("verify-input", MEASUREMENTS);
+        let original = report_with(MEASUREMENTS, &[]);
+        let verify = |reference: &str| {
+            let reference = TempFile::with("reference", reference.as_bytes());
+            let args = [
+                "verify",
+                "--reference",
+                reference.arg(),
+                "--quiet",
+                input.arg(),
+            ];
+            run(args.map(String::from))
+        };
+        verify(&original).unwrap();
+        let err = verify(&original.replace("Cracow=12.6", "Cracow=12.7")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Station \"Cracow\" differs: expected 12.7/12.6/12.6, got 12.6/12.6/12.6"
+        );
+        assert!(Config::from_args(["verify".to_string()]).is_err());
+        assert!(Config::from_args(["--reference", "x"].map(String::from)).is_err());
+    }
+
+    #[test]
+    fn fixed_reports_round_trip() {
+        let original = report_with(MEASUREMENTS, &[]);
+        let report = TempFile::with("old-report", original.as_bytes());
+        let (fixed, refixed) = (TempFile::new("fixed"), TempFile::new("refixed"));
+        for (input, output) in [(&report, &fixed), (&fixed, &refixed)] {
+            let args = [
+                "fix-output",
+                input.arg(),
+                "--quiet",
+                "--output",
+                output.arg(),
+            ];
+            run(args.map(String::from)).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&fixed.0).unwrap(), original);
+        assert_eq!(fs::read_to_string(&refixed.0).unwrap(), original);
+    }
+
+    #[test]
+    fn mean_buckets_follow_the_displayed_mean() {
+        let precision = Precision::default();
+        let five = NonZero::new(5).unwrap();
+        // The exact means are 1.45 and -1.55, but they display as 1.5 and -1.5, which start and end
+        // bands of width 0.5.
+        for (values, shown, bucket) in [([14, 15], "1.5", 3), ([-15, -16], "-1.5", -3)] {
+            let stat = values.into_iter().fold(Stat::default(), Stat::observe);
+            assert!(
+                stat.display(precision)
+                    .to_string()
+                    .contains(&format!("/{shown}/"))
+            );
+            assert_eq!(stat.mean_bucket(five, precision), Some(bucket));
+        }
+        assert_eq!(Stat::new(0).mean_bucket(five, precision), Some(0));
+        assert_eq!(Stat::new(-1).mean_bucket(five, precision), Some(-1));
+        assert_eq!(Stat::default().mean_bucket(five, precision), None);
+        for width in ["0", "0.04", "-0.5"] {
+            let args = ["--format", "ndjson", "--band-width", width].map(String::from);
+            assert!(Config::from_args(args).is_err(), "{width}");
+        }
+    }
+
+    #[test]
+    fn fan_in_counts_the_chunks_a_station_spans() {
+        let config = Config::default();
+        let data = b"A;1.0\nB;2.0\nA;3.0\n";
+        let chunks = chunk_data(data, NonZero::new(2).unwrap(), &config.record_delim);
+        assert_eq!(chunks.len(), 2);
+        let visitors = chunks
+            .iter()
+            .map(|chunk| {
+                process_chunk::<StatVisitor>(chunk, 0, &config, &RunState::new(&config))
+                    .unwrap()
+                    .visitor
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(fan_in(visitors.iter()), [(&b"A"[..], 2), (&b"B"[..], 1)]);
+    }
+
+    #[test]
+    fn integer_tenths() {
+        assert_eq!(
+            report_with(b"A;123\nB;-45\nC;0\n", &["--integer-tenths"]),
+            "Num stations: 3\n{A=12.3/12.3/12.3, B=-4.5/-4.5/-4.5, C=0.0/0.0/0.0}\n"
+        );
+        assert!(parse_integer_tenths(b"12.3").is_err());
+        assert!(parse_integer_tenths(b"40000").is_err());
+    }
+
+    #[test]
+    fn chunks_are_capped_by_lines() {
+        let data = b"A;1.0\n".repeat(100);
+        let chunks = chunk_data(&data, NonZero::new(256).unwrap(), &Delimiter::new(b"\n"));
+        assert!(chunks.len() <= 100, "{} chunks", chunks.len());
+        assert!(chunks.iter().all(|chunk| chunk.ends_with(b"\n")));
+        assert_eq!(chunks.concat(), data);
+        assert_eq!(
+            report_with(&data, &["--chunks", "256"]),
+            "Num stations: 1\n{A=1.0/1.0/1.0}\n"
+        );
+    }
+
+    #[test]
+    fn one_long_line_is_one_chunk() {
+        let mut data = vec![b'A'; 10_000];
+        data.extend_from_slice(b";1.0\n");
+        let chunks = chunk_data(&data, NonZero::new(64).unwrap(), &Delimiter::new(b"\n"));
+        assert_eq!(chunks.len(), 1, "{} chunks", chunks.len());
+        assert_eq!(chunks[0], data);
+
+        data.extend_from_slice(&b"B;2.0\n".repeat(3));
+        let chunks = chunk_data(&data, NonZero::new(64).unwrap(), &Delimiter::new(b"\n"));
+        assert!(chunks.iter().all(|chunk| !chunk.is_empty()));
+        assert!(chunks.iter().all(|chunk| chunk.ends_with(b"\n")));
+        assert_eq!(chunks.concat(), data);
+    }
+
+    #[test]
+    fn configs_can_be_reused() {
+        for args in [
+            &["--chunks", "1", "--limit-lines", "4"][..],
+            &["--chunks", "3", "--sample-rate", "0.5", "--seed", "7"],
+        ] {
+            let config = Config::from_args(args.iter().map(|arg| arg.to_string())).unwrap();
+            let first = report(MEASUREMENTS, &config).unwrap();
+            assert_ne!(first, "Num stations: 0\n{}\n", "{args:?}");
+            assert_eq!(report(MEASUREMENTS, &config).unwrap(), first, "{args:?}");
+        }
+    }
+
+    #[test]
+    fn too_many_stations_fail_with_the_limit() {
+        // Sorted input counts the station of the run in progress, which MEASUREMENTS revisits.
+        for sorted in [&[][..], &["--sorted-input"]] {
+            let args = [&["--quiet", "--max-stations", "4"][..], sorted].concat();
+            let config = Config::from_args(args.into_iter().map(String::from)).unwrap();
+            let err = report(MEASUREMENTS, &config).unwrap_err();
+            assert!(
+                err.chain().any(|cause| matches!(
+                    cause.downcast_ref(),
+                    Some(ParseError::TooManyStations { limit: 4 })
+                )),
+                "{err:#}"
+            );
+            report_with(
+                MEASUREMENTS,
+                &[&["--max-stations", "5"][..], sorted].concat(),
+            );
+        }
+    }
+
+    #[test]
+    fn help_stops_parsing() {
+        for args in [
+            &["-h"][..],
+            &["--help"],
+            &["--summary", "--help", "--bogus"],
+        ] {
+            let config = Config::from_args(args.iter().map(|arg| arg.to_string())).unwrap();
+            assert!(config.help);
+        }
+    }
+}
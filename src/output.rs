@@ -0,0 +1,561 @@
+use std::{
+    array,
+    cmp::Ordering,
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufWriter, ErrorKind, Write, stdout},
+    iter,
+    path::Path,
+};
+
+use anyhow::{Context, Result, bail};
+use gxhash::HashMap;
+#[cfg(feature = "mmap")]
+use memmap2::MmapMut;
+
+use crate::{
+    Stat,
+    config::{Config, OutputFormat, Precision},
+};
+
+/// Writes the report to `--output` if given, or stdout otherwise.
+pub fn write_output(stations: &HashMap<&[u8], Stat>, config: &Config) -> Result<()> {
+    write_to(config, |writer| write_report(writer, stations, config))
+}
+
+/// Writes the `--time-window` report to `--output` if given, or stdout otherwise.
+pub fn write_windows(
+    windows: &HashMap<Box<[u8]>, BTreeMap<i64, Stat>>,
+    config: &Config,
+) -> Result<()> {
+    write_to(config, |writer| {
+        print_windows(writer, windows, config.precision)
+    })
+}
+
+/// Runs `write` on `--output` if given, or stdout otherwise.
+fn write_to(config: &Config, write: impl Fn(&mut dyn Write) -> Result<()>) -> Result<()> {
+    let Some(path) = &config.output else {
+        return match write(&mut BufWriter::new(stdout().lock())) {
+            // Whoever was reading stopped early, as `head` does, which isn't a failure of ours.
+            Err(err) if is_broken_pipe(&err) => Ok(()),
+            result => result,
+        };
+    };
+    if config.mmap_output {
+        // A first pass that only counts bytes sizes the file, so the report is never held whole.
+        let mut len = ByteCount(0);
+        write(&mut len)?;
+        match write_mmap(path, len.0, &write) {
+            Ok(()) => return Ok(()),
+            Err(err) if !config.quiet => eprintln!("Falling back to buffered output: {err:#}"),
+            Err(_) => {}
+        }
+    }
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create output file {}", path.display()))?;
+    write(&mut BufWriter::new(file))
+}
+
+/// Discards what's written to it, only counting the bytes.
+struct ByteCount(u64);
+
+impl Write for ByteCount {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub(crate) fn is_broken_pipe(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<io::Error>())
+        .any(|err| err.kind() == ErrorKind::BrokenPipe)
+}
+
+/// Writes the report for `stations` in the configured format.
+///
+/// With `--no-sort` every format but the table streams entries straight from the map, so memory
+/// stays at roughly the map itself. Sorting first copies every entry into a `Vec`, and the table
+/// also formats every row up front to find the column widths. `--mmap-output` formats the report
+/// twice, once to size the file and once into it.
+pub(crate) fn write_report<W: Write>(
+    mut writer: W,
+    stations: &HashMap<&[u8], Stat>,
+    config: &Config,
+) -> Result<()> {
+    if config.no_sort {
+        write_items(&mut writer, stations.iter().map(|(&k, &v)| (k, v)), config)
+    } else {
+        write_items(&mut writer, sort_stations(stations).into_iter(), config)
+    }
+    .context("Failed to display results")?;
+    if config.summary && !config.stations_only && config.format == OutputFormat::Text {
+        let summary = Stat::merge_all(stations.values().copied()).unwrap_or_default();
+        writeln!(writer, "Summary: {}", summary.display(config.precision))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_items<'a, W: Write>(
+    mut writer: W,
+    items: impl ExactSizeIterator<Item = (&'a [u8], Stat)>,
+    config: &Config,
+) -> Result<()> {
+    if config.stations_only {
+        print_names(&mut writer, items.map(|(k, _)| k))
+    } else {
+        match config.format {
+            OutputFormat::Text => {
+                writeln!(writer, "Num stations: {}", items.len())?;
+                print(&mut writer, items, config.precision, config.first_last).map(drop)
+            }
+            OutputFormat::Ndjson => print_ndjson(&mut writer, items, config),
+            OutputFormat::Tsv => print_tsv(&mut writer, items, config.precision, config.first_last),
+            OutputFormat::Msgpack => print_msgpack(&mut writer, items, config.precision),
+            OutputFormat::Table => print_table(&mut writer, items, config.precision),
+        }
+    }
+}
+
+pub fn sort_stations<'a>(stations: &HashMap<&'a [u8], Stat>) -> Vec<(&'a [u8], Stat)> {
+    let mut sorted = stations.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>();
+    sorted.sort_unstable_by(|(a, _), (b, _)| reference_order(a, b));
+    sorted
+}
+
+/// Orders station names the way the reference implementation's `TreeMap<String, _>` does, which
+/// compares UTF-16 code units.
+///
+/// That is byte order, except characters above U+FFFF sort before U+E000..=U+FFFF because their
+/// surrogates come first. Those are the only characters with lead bytes 0xF0.. and 0xEE..=0xEF, and
+/// continuation bytes never take those values, so moving 0xEE and 0xEF past every other byte is
+/// enough. Names that aren't UTF-8 still get a consistent order.
+pub fn reference_order(a: &[u8], b: &[u8]) -> Ordering {
+    let rank = |&byte: &u8| match byte {
+        0xEE | 0xEF => u16::from(byte) + 0x100,
+        _ => u16::from(byte),
+    };
+    a.iter().map(rank).cmp(b.iter().map(rank))
+}
+
+/// Writes the `{name=min/mean/max, ...}` map, or `{name=min/mean/max/first/last, ...}` with
+/// `first_last`, returning how many bytes that took.
+pub fn print<'a, W: Write>(
+    mut writer: W,
+    sorted_items: impl Iterator<Item = (&'a [u8], Stat)>,
+    precision: Precision,
+    first_last: bool,
+) -> Result<u64> {
+    writer.write_all(b"{")?;
+    let mut written = 1;
+    // Each entry is formatted into a reused scratch buffer so it reaches the writer in one call.
+    let mut entry = Vec::with_capacity(128);
+    let mut peekable = sorted_items.peekable();
+    while let Some((station, stat)) = peekable.next() {
+        entry.clear();
+        entry.extend_from_slice(station);
+        write!(entry, "={}", stat.display(precision))?;
+        if first_last {
+            match stat.ends(precision) {
+                Some([first, last]) => write!(entry, "/{first}/{last}")?,
+                None => entry.extend_from_slice(b"/-/-"),
+            }
+        }
+        if peekable.peek().is_some() {
+            entry.extend_from_slice(b", ");
+        }
+        writer.write_all(&entry)?;
+        written += entry.len() as u64;
+    }
+    writer.write_all(b"}\n")?;
+    Ok(written + 2)
+}
+
+/// Writes each station as a line holding a JSON object with its name and stats, including `first`
+/// and `last` with `--first-last` and `band` with `--band-width`. Names must be UTF-8, since JSON
+/// strings can't hold anything else.
+fn print_ndjson<'a, W: Write>(
+    mut writer: W,
+    items: impl Iterator<Item = (&'a [u8], Stat)>,
+    config: &Config,
+) -> Result<()> {
+    let precision = config.precision;
+    let mut line = Vec::with_capacity(128);
+    for (station, stat) in items {
+        let name = str::from_utf8(station).with_context(|| {
+            format!(
+                "Station name is not valid UTF-8: \"{}\"",
+                station.escape_ascii()
+            )
+        })?;
+        line.clear();
+        line.extend_from_slice(b"{\"station\":");
+        write_json_string(&mut line, name)?;
+        match stat.columns(precision) {
+            Some([min, mean, max]) => write!(line, ",\"min\":{min},\"mean\":{mean},\"max\":{max}")?,
+            None => line.extend_from_slice(b",\"min\":null,\"mean\":null,\"max\":null"),
+        }
+        if config.first_last {
+            match stat.ends(precision) {
+                Some([first, last]) => write!(line, ",\"first\":{first},\"last\":{last}")?,
+                None => line.extend_from_slice(b",\"first\":null,\"last\":null"),
+            }
+        }
+        if let Some(width) = config.band_width {
+            match stat.mean_bucket(width, precision) {
+                Some(band) => write!(line, ",\"band\":{band}")?,
+                None => line.extend_from_slice(b",\"band\":null"),
+            }
+        }
+        line.extend_from_slice(b"}\n");
+        writer.write_all(&line)?;
+    }
+    Ok(())
+}
+
+/// Writes a line holding a JSON object per station and time window, with the station's name, the
+/// window's first second and its stats. Stations come in reference order and windows in time
+/// order. Names must be UTF-8, since JSON strings can't hold anything else.
+fn print_windows<W: Write>(
+    mut writer: W,
+    windows: &HashMap<Box<[u8]>, BTreeMap<i64, Stat>>,
+    precision: Precision,
+) -> Result<()> {
+    let mut stations = windows.iter().collect::<Vec<_>>();
+    stations.sort_unstable_by(|(a, _), (b, _)| reference_order(a, b));
+    let mut line = Vec::with_capacity(128);
+    for (station, station_windows) in stations {
+        let name = str::from_utf8(station).with_context(|| {
+            format!(
+                "Station name is not valid UTF-8: \"{}\"",
+                station.escape_ascii()
+            )
+        })?;
+        for (start, stat) in station_windows {
+            line.clear();
+            line.extend_from_slice(b"{\"station\":");
+            write_json_string(&mut line, name)?;
+            write!(line, ",\"window\":{start}")?;
+            match stat.columns(precision) {
+                Some([min, mean, max]) => {
+                    write!(line, ",\"min\":{min},\"mean\":{mean},\"max\":{max}}}")?
+                }
+                None => line.extend_from_slice(b",\"min\":null,\"mean\":null,\"max\":null}"),
+            }
+            line.push(b'\n');
+            writer.write_all(&line)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes each station as a line of its name, min, mean and max, then first and last with
+/// `first_last`, separated by tabs, with no quoting. A name containing a tab or newline would be
+/// ambiguous, so it's an error instead.
+fn print_tsv<'a, W: Write>(
+    mut writer: W,
+    items: impl Iterator<Item = (&'a [u8], Stat)>,
+    precision: Precision,
+    first_last: bool,
+) -> Result<()> {
+    let mut line = Vec::with_capacity(128);
+    for (station, stat) in items {
+        if station
+            .iter()
+            .any(|&byte| matches!(byte, b'\t' | b'\n' | b'\r'))
+        {
+            bail!(
+                "Station name \"{}\" can't be written as TSV",
+                station.escape_ascii()
+            );
+        }
+        line.clear();
+        line.extend_from_slice(station);
+        match stat.columns(precision) {
+            Some([min, mean, max]) => write!(line, "\t{min}\t{mean}\t{max}")?,
+            None => line.extend_from_slice(b"\t\t\t"),
+        }
+        if first_last {
+            match stat.ends(precision) {
+                Some([first, last]) => write!(line, "\t{first}\t{last}")?,
+                None => line.extend_from_slice(b"\t\t"),
+            }
+        }
+        line.push(b'\n');
+        writer.write_all(&line)?;
+    }
+    Ok(())
+}
+
+/// Writes a header and a line per station with its name padded to the longest one, then its min,
+/// mean and max right-aligned in columns, for reading in a terminal. A station with no
+/// observations shows `-` in each column.
+fn print_table<'a, W: Write>(
+    mut writer: W,
+    items: impl Iterator<Item = (&'a [u8], Stat)>,
+    precision: Precision,
+) -> Result<()> {
+    // Widths are only known once every row is formatted.
+    let rows = items
+        .map(|(station, stat)| {
+            let columns = match stat.columns(precision) {
+                Some(columns) => columns.map(|column| column.to_string()),
+                None => ["-", "-", "-"].map(String::from),
+            };
+            (String::from_utf8_lossy(station), columns)
+        })
+        .collect::<Vec<_>>();
+    let header = ("Station".into(), ["Min", "Mean", "Max"].map(String::from));
+    let name_width = iter::once(&header)
+        .chain(&rows)
+        .map(|(name, _)| name.chars().count())
+        .max()
+        .unwrap_or_default();
+    let widths: [usize; 3] = array::from_fn(|idx| {
+        iter::once(&header)
+            .chain(&rows)
+            .map(|(_, columns)| columns[idx].len())
+            .max()
+            .unwrap_or_default()
+    });
+    for (name, [min, mean, max]) in iter::once(&header).chain(&rows) {
+        let [min_width, mean_width, max_width] = widths;
+        writeln!(
+            writer,
+            "{name:<name_width$}  {min:>min_width$}  {mean:>mean_width$}  {max:>max_width$}"
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes the stations as one MessagePack map from each name, as a str, to an array of its min,
+/// mean and max as float64s, all `nil` for a station with no observations. Entries are in the same
+/// order as the text report. Names must be UTF-8, as MessagePack strs are.
+fn print_msgpack<'a, W: Write>(
+    mut writer: W,
+    items: impl ExactSizeIterator<Item = (&'a [u8], Stat)>,
+    precision: Precision,
+) -> Result<()> {
+    let len = u32::try_from(items.len()).context("Too many stations for a MessagePack map")?;
+    let mut entry = Vec::with_capacity(128);
+    msgpack_header(&mut entry, len, 0x80, 0xde);
+    writer.write_all(&entry)?;
+    for (station, stat) in items {
+        entry.clear();
+        let name = str::from_utf8(station).with_context(|| {
+            format!(
+                "Station name is not valid UTF-8: \"{}\"",
+                station.escape_ascii()
+            )
+        })?;
+        msgpack_str(&mut entry, name);
+        entry.push(0x93);
+        match stat.columns(precision) {
+            Some(columns) => {
+                for column in columns {
+                    entry.push(0xcb);
+                    entry.extend_from_slice(&column.to_f64().to_be_bytes());
+                }
+            }
+            None => entry.extend_from_slice(&[0xc0; 3]),
+        }
+        writer.write_all(&entry)?;
+    }
+    Ok(())
+}
+
+fn msgpack_str(out: &mut Vec<u8>, s: &str) {
+    let len = s.len() as u32;
+    if len < 32 {
+        out.push(0xa0 | len as u8);
+    } else if let Ok(len) = u8::try_from(len) {
+        out.extend_from_slice(&[0xd9, len]);
+    } else {
+        msgpack_header(out, len, 0xa0, 0xda);
+    }
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Writes a map or str length, as a fix type below 16 or with the 16 or 32 bit type after `wide`.
+fn msgpack_header(out: &mut Vec<u8>, len: u32, fix: u8, wide: u8) {
+    if len < 16 {
+        out.push(fix | len as u8);
+    } else if let Ok(len) = u16::try_from(len) {
+        out.push(wide);
+        out.extend_from_slice(&len.to_be_bytes());
+    } else {
+        out.push(wide + 1);
+        out.extend_from_slice(&len.to_be_bytes());
+    }
+}
+
+/// Writes, as a JSON array, the byte range of every chunk the inputs would be split into. Each
+/// range starts at the beginning of a record and ends after a record delimiter, or at the end of
+/// its file, so it can be processed on its own.
+pub fn write_plan(plan: &[(&Path, usize, usize)]) -> Result<()> {
+    let mut out = Vec::new();
+    out.push(b'[');
+    for (i, (path, start, len)) in plan.iter().enumerate() {
+        if i > 0 {
+            out.push(b',');
+        }
+        out.extend_from_slice(b"{\"path\":");
+        write_json_string(&mut out, &path.to_string_lossy())?;
+        write!(out, ",\"start\":{start},\"len\":{len}}}")?;
+    }
+    out.extend_from_slice(b"]\n");
+    stdout().lock().write_all(&out)?;
+    Ok(())
+}
+
+fn write_json_string(out: &mut Vec<u8>, s: &str) -> io::Result<()> {
+    out.push(b'"');
+    for byte in s.bytes() {
+        match byte {
+            b'"' => out.extend_from_slice(b"\\\""),
+            b'\\' => out.extend_from_slice(b"\\\\"),
+            b'\n' => out.extend_from_slice(b"\\n"),
+            b'\r' => out.extend_from_slice(b"\\r"),
+            b'\t' => out.extend_from_slice(b"\\t"),
+            0..0x20 => write!(out, "\\u{byte:04x}")?,
+            _ => out.push(byte),
+        }
+    }
+    out.push(b'"');
+    Ok(())
+}
+
+fn print_names<'a, W: Write>(mut writer: W, names: impl Iterator<Item = &'a [u8]>) -> Result<()> {
+    for name in names {
+        writer.write_all(name)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "mmap"))]
+fn write_mmap(_: &Path, _: u64, _: &dyn Fn(&mut dyn Write) -> Result<()>) -> Result<()> {
+    bail!("Built without mmap support")
+}
+
+/// Sizes the file at `path` to `len` bytes and runs `write` on a writable mapping of it, which
+/// must write exactly that many.
+#[cfg(feature = "mmap")]
+fn write_mmap(path: &Path, len: u64, write: &dyn Fn(&mut dyn Write) -> Result<()>) -> Result<()> {
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .with_context(|| format!("Failed to open output file {}", path.display()))?;
+    if !file.metadata()?.is_file() {
+        bail!("{} is not a regular file", path.display());
+    }
+    file.set_len(len).context("Failed to size output file")?;
+    // SAFTEY: We just truncated this file and nothing else writes to it while it's mapped.
+    let mut map = unsafe { MmapMut::map_mut(&file) }.context("Failed to mmap output file")?;
+    let mut rest = &mut map[..];
+    write(&mut rest)?;
+    if !rest.is_empty() {
+        bail!(
+            "The report came out {} bytes shorter than sized",
+            rest.len()
+        );
+    }
+    map.flush().context("Failed to flush mmapped output")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn str_bytes(len: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        msgpack_str(&mut out, &"a".repeat(len));
+        assert_eq!(&out[out.len() - len..], "a".repeat(len).as_bytes());
+        out.truncate(out.len() - len);
+        out
+    }
+
+    fn map_header(len: u32) -> Vec<u8> {
+        let mut out = Vec::new();
+        msgpack_header(&mut out, len, 0x80, 0xde);
+        out
+    }
+
+    #[test]
+    fn msgpack_str_lengths() {
+        assert_eq!(str_bytes(0), [0xa0]);
+        assert_eq!(str_bytes(31), [0xbf]);
+        assert_eq!(str_bytes(32), [0xd9, 32]);
+        assert_eq!(str_bytes(255), [0xd9, 255]);
+        assert_eq!(str_bytes(256), [0xda, 0x01, 0x00]);
+        assert_eq!(str_bytes(65_535), [0xda, 0xff, 0xff]);
+        assert_eq!(str_bytes(65_536), [0xdb, 0x00, 0x01, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn msgpack_map_lengths() {
+        assert_eq!(map_header(0), [0x80]);
+        assert_eq!(map_header(15), [0x8f]);
+        assert_eq!(map_header(16), [0xde, 0x00, 0x10]);
+        assert_eq!(map_header(65_535), [0xde, 0xff, 0xff]);
+        assert_eq!(map_header(65_536), [0xdf, 0x00, 0x01, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn msgpack_entries() {
+        let stat = [-75, 342].into_iter().fold(Stat::default(), Stat::observe);
+        let items = [(&b"Abha"[..], stat), (&b"Aden"[..], Stat::default())];
+        let mut out = Vec::new();
+        print_msgpack(&mut out, items.into_iter(), Precision::default()).unwrap();
+        let mut expected = vec![0x82, 0xa4];
+        expected.extend_from_slice(b"Abha");
+        expected.push(0x93);
+        for column in [-7.5f64, 13.4, 34.2] {
+            expected.push(0xcb);
+            expected.extend_from_slice(&column.to_be_bytes());
+        }
+        expected.push(0xa4);
+        expected.extend_from_slice(b"Aden");
+        expected.extend_from_slice(&[0x93, 0xc0, 0xc0, 0xc0]);
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn stations_sort_like_the_reference() {
+        // The order the reference implementation prints. The last two are the only pair where
+        // UTF-16 order differs from byte order: U+1D504 is stored as surrogates, which come before
+        // U+FF21.
+        let expected = [
+            "Abha",
+            "Saint-Denis",
+            "San Jose",
+            "San José",
+            "Zürich",
+            "Ürümqi",
+            "\u{1D504}lpha",
+            "\u{FF21}rdmore",
+        ];
+        let stations = expected
+            .iter()
+            .rev()
+            .map(|name| (name.as_bytes(), Stat::default()))
+            .collect();
+        let sorted = sort_stations(&stations)
+            .into_iter()
+            .map(|(name, _)| str::from_utf8(name).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(sorted, expected);
+        assert!(expected[6].as_bytes() > expected[7].as_bytes());
+    }
+}
@@ -0,0 +1,95 @@
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+
+use crate::{config::Config, input::is_stream, parse_decimal, records, split_line, split_unit};
+
+/// How many valid lines are looked at before settling on a scale.
+const SAMPLE_LINES: usize = 1000;
+/// How much of the input is read to find them.
+const SAMPLE_BYTES: u64 = 1 << 20;
+
+/// Finds whether the values in `paths` have one or two decimal places, failing if they don't
+/// agree, since stats at different scales can't be aggregated together.
+///
+/// Only the start of each file is looked at, and values further in with a different scale are
+/// still malformed. Files with no values to go by are left out, and if none have any the
+/// challenge's one place is assumed. Streams are refused, since sampling one would use up the
+/// lines the run needs.
+pub fn detect(paths: &[PathBuf], config: &Config) -> Result<u32> {
+    let mut found: Option<(u32, &Path)> = None;
+    for path in paths {
+        if is_stream(path) {
+            bail!(
+                "--auto-scale can't sample {}, since it can only be read once",
+                path.display()
+            );
+        }
+        let Some(scale) = detect_file(path, config)? else {
+            continue;
+        };
+        match found {
+            Some((first, first_path)) if first != scale => bail!(
+                "{} has values with {first} decimal places but {} has {scale}",
+                first_path.display(),
+                path.display()
+            ),
+            Some(_) => {}
+            None => found = Some((scale, path)),
+        }
+    }
+    let scale = found.map_or(1, |(scale, _)| scale);
+    if scale == 2 {
+        eprintln!("Detected values with two decimal places");
+    }
+    Ok(scale)
+}
+
+/// Finds whether the values at the start of `path` have one or two decimal places, failing if it
+/// has both, or `None` if it has neither.
+///
+/// Only lines with a plain decimal value of one or two places count towards [`SAMPLE_LINES`].
+fn detect_file(path: &Path, config: &Config) -> Result<Option<u32>> {
+    let mut data = Vec::new();
+    File::open(path)
+        .and_then(|file| file.take(SAMPLE_BYTES).read_to_end(&mut data))
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    // The line each scale was first seen on, for one and two places.
+    let mut first = [None; 2];
+    let mut valid = 0;
+    for (idx, line) in records(&data, &config.record_delim).enumerate() {
+        let Ok((_, value)) = split_line(line, config) else {
+            continue;
+        };
+        let value = if config.strip_unit {
+            split_unit(value).0
+        } else {
+            value
+        };
+        let places = value
+            .iter()
+            .position(|&byte| byte == b'.')
+            .map_or(0, |point| value.len() - point - 1);
+        if !(1..=2).contains(&places) || parse_decimal(value).is_none() {
+            continue;
+        }
+        first[places - 1].get_or_insert(idx + 1);
+        valid += 1;
+        if valid == SAMPLE_LINES {
+            break;
+        }
+    }
+    match first {
+        [Some(one), Some(two)] => bail!(
+            "{} mixes values with one decimal place (line {one}) and two (line {two})",
+            path.display()
+        ),
+        [Some(_), None] => Ok(Some(1)),
+        [None, Some(_)] => Ok(Some(2)),
+        [None, None] => Ok(None),
+    }
+}
@@ -0,0 +1,158 @@
+use std::{fs, io::ErrorKind, path::Path};
+
+use anyhow::{Context, Result, bail};
+use gxhash::{HashMap, HashMapExt};
+
+use crate::Stat;
+
+/// Identifies a snapshot file and its layout version.
+const MAGIC: &[u8; 8] = b"BRCSNAP2";
+/// The first layout, which had no scale and always held tenths.
+const MAGIC_V1: &[u8; 8] = b"BRCSNAP1";
+
+/// The stations of a snapshot, along with how many decimal places their values have.
+pub struct Snapshot<'a> {
+    pub scale: u32,
+    pub stations: HashMap<&'a [u8], Stat>,
+}
+
+/// Reads a snapshot written by [`write`], or returns `None` if `path` doesn't exist yet.
+pub fn read(path: &Path) -> Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("Failed to read snapshot {}", path.display())),
+    }
+}
+
+/// Decodes the stations in a snapshot, borrowing their names from `bytes`.
+///
+/// The layout is [`MAGIC`], the scale as a `u8`, the station count as a little-endian `u64`, then
+/// for each station its name length as a `u32`, the name, `min` and `max` as `i16`, `total` as an
+/// `i64` and `count` as a `u32`, all little-endian. That keeps every field of [`Stat`] exactly, so
+/// merging a snapshot is the same as having aggregated its lines again. [`MAGIC_V1`] snapshots
+/// have no scale byte.
+pub fn decode(bytes: &[u8]) -> Result<Snapshot<'_>> {
+    let mut reader = Reader(bytes);
+    let scale = match reader.take(MAGIC.len())? {
+        magic if magic == MAGIC => u32::from(u8::from_le_bytes(reader.array()?)),
+        magic if magic == MAGIC_V1 => 1,
+        _ => bail!("Not a snapshot file"),
+    };
+    let len = u64::from_le_bytes(reader.array()?);
+    let mut stations = HashMap::with_capacity(len.min(1 << 16) as usize);
+    for _ in 0..len {
+        let name_len = u32::from_le_bytes(reader.array()?) as usize;
+        let name = reader.take(name_len)?;
+        let (min, max) = (
+            i16::from_le_bytes(reader.array()?),
+            i16::from_le_bytes(reader.array()?),
+        );
+        let stat = Stat {
+            min,
+            max,
+            total: i64::from_le_bytes(reader.array()?),
+            count: u32::from_le_bytes(reader.array()?),
+            // Snapshots don't keep the order of values, which `--first-last` refuses to mix with.
+            first: min,
+            last: max,
+        };
+        if stations.insert(name, stat).is_some() {
+            bail!("Station \"{}\" appears twice", name.escape_ascii());
+        }
+    }
+    if !reader.0.is_empty() {
+        bail!("Trailing bytes after the last station");
+    }
+    Ok(Snapshot { scale, stations })
+}
+
+/// Writes `stations`, whose values have `scale` decimal places, to `path` in the format [`decode`]
+/// reads.
+pub fn write(path: &Path, stations: &HashMap<&[u8], Stat>, scale: u32) -> Result<()> {
+    let mut bytes = Vec::with_capacity(17 + stations.len() * 32);
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(u8::try_from(scale).context("Scale too large for a snapshot")?);
+    bytes.extend_from_slice(&(stations.len() as u64).to_le_bytes());
+    for (name, stat) in stations {
+        let name_len = u32::try_from(name.len()).context("Station name too long for a snapshot")?;
+        bytes.extend_from_slice(&name_len.to_le_bytes());
+        bytes.extend_from_slice(name);
+        bytes.extend_from_slice(&stat.min().to_le_bytes());
+        bytes.extend_from_slice(&stat.max().to_le_bytes());
+        bytes.extend_from_slice(&stat.total().to_le_bytes());
+        bytes.extend_from_slice(&stat.count().to_le_bytes());
+    }
+    fs::write(path, bytes).with_context(|| format!("Failed to write snapshot {}", path.display()))
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let Some((head, rest)) = self.0.split_at_checked(len) else {
+            bail!("Snapshot ends unexpectedly");
+        };
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().expect("took exactly N bytes"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    #[test]
+    fn round_trips_every_field() {
+        let stations = HashMap::from_iter([
+            (
+                &b"Hamburg"[..],
+                [120, -75, 342]
+                    .into_iter()
+                    .fold(Stat::default(), Stat::observe),
+            ),
+            (&b"Abha"[..], Stat::new(i16::MIN)),
+            (&b""[..], Stat::new(i16::MAX)),
+        ]);
+        let path = env::temp_dir().join(format!("brc-test-{}-snapshot", process::id()));
+        write(&path, &stations, 2).unwrap();
+        let bytes = read(&path).unwrap().unwrap();
+        fs::remove_file(&path).unwrap();
+        let snapshot = decode(&bytes).unwrap();
+        assert_eq!(snapshot.scale, 2);
+        assert_eq!(snapshot.stations.len(), stations.len());
+        for (name, stat) in &stations {
+            let decoded = snapshot.stations[name];
+            assert_eq!(
+                (
+                    decoded.min(),
+                    decoded.max(),
+                    decoded.total(),
+                    decoded.count()
+                ),
+                (stat.min(), stat.max(), stat.total(), stat.count())
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_snapshots() {
+        let path = env::temp_dir().join(format!("brc-test-{}-truncated", process::id()));
+        write(
+            &path,
+            &HashMap::from_iter([(&b"Abha"[..], Stat::new(1))]),
+            1,
+        )
+        .unwrap();
+        let bytes = read(&path).unwrap().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decode(&[bytes.as_slice(), b"x"].concat()).is_err());
+    }
+}
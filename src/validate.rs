@@ -0,0 +1,84 @@
+use std::{num::NonZero, path::PathBuf, time::Instant};
+
+use anyhow::{Result, bail};
+#[cfg(feature = "rayon")]
+use rayon::iter::ParallelIterator;
+
+use crate::{
+    config::Config, error::ParseError, input::map_files, input_chunks, par_iter, parse_line,
+    records, slices,
+};
+
+/// How many invalid lines are kept to show as examples.
+const SAMPLES: usize = 10;
+
+/// Counts of the lines in some chunks, with the first few invalid ones.
+#[derive(Default)]
+struct Validation {
+    valid: u64,
+    invalid: u64,
+    samples: Vec<(Box<[u8]>, ParseError)>,
+}
+
+impl Validation {
+    fn merge(&mut self, other: Self) {
+        self.valid += other.valid;
+        self.invalid += other.invalid;
+        let room = SAMPLES - self.samples.len();
+        self.samples.extend(other.samples.into_iter().take(room));
+    }
+}
+
+/// Parses every line of the inputs without aggregating them, reporting how many are invalid with
+/// a few examples, and fails if any are.
+pub fn validate(
+    paths: &[PathBuf],
+    max_open: usize,
+    parts: NonZero<usize>,
+    config: &Config,
+) -> Result<()> {
+    let start = Instant::now();
+    let mut validation = Validation::default();
+    for wave in paths.chunks(max_open) {
+        let maps = map_files(wave, config)?;
+        let chunks = slices(&maps, config)
+            .into_iter()
+            .flat_map(|data| input_chunks(data, parts, config))
+            .collect::<Vec<_>>();
+        let results = par_iter(chunks)
+            .map(|chunk| validate_chunk(chunk, config))
+            .collect::<Vec<_>>();
+        for result in results {
+            validation.merge(result);
+        }
+    }
+    let elapsed = start.elapsed();
+    let lines = validation.valid + validation.invalid;
+    for (line, err) in &validation.samples {
+        eprintln!("Invalid line \"{}\": {err}", line.escape_ascii());
+    }
+    eprintln!(
+        "Validated {lines} lines in {elapsed:.2?} ({:.0} lines/s)",
+        lines as f64 / elapsed.as_secs_f64()
+    );
+    if validation.invalid > 0 {
+        bail!("{} of {lines} lines are invalid", validation.invalid);
+    }
+    Ok(())
+}
+
+fn validate_chunk(data: &[u8], config: &Config) -> Validation {
+    let mut validation = Validation::default();
+    for line in records(data, &config.record_delim) {
+        match parse_line(line, config) {
+            Ok(_) => validation.valid += 1,
+            Err(err) => {
+                validation.invalid += 1;
+                if validation.samples.len() < SAMPLES {
+                    validation.samples.push((line.into(), err));
+                }
+            }
+        }
+    }
+    validation
+}
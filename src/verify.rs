@@ -0,0 +1,97 @@
+use std::{fs, path::Path};
+
+use anyhow::{Context, Result, bail};
+use gxhash::HashMap;
+use memchr::memmem;
+
+use crate::{
+    Stat,
+    config::Config,
+    output::{print, reference_order, sort_stations},
+};
+
+/// Checks that the report for `stations` matches the `{...}` map in the file at `reference`,
+/// failing with the first station that differs if it doesn't.
+pub fn verify(stations: &HashMap<&[u8], Stat>, config: &Config, reference: &Path) -> Result<()> {
+    let bytes = fs::read(reference)
+        .with_context(|| format!("Failed to read reference {}", reference.display()))?;
+    let expected = bytes
+        .split(|&byte| byte == b'\n')
+        .find(|line| line.starts_with(b"{"))
+        .with_context(|| format!("No {{...}} map in reference {}", reference.display()))?;
+    let mut actual = Vec::new();
+    print(
+        &mut actual,
+        sort_stations(stations).into_iter(),
+        config.precision,
+        false,
+    )?;
+    if actual.strip_suffix(b"\n") == Some(expected) {
+        eprintln!("Output matches {}", reference.display());
+        return Ok(());
+    }
+
+    // Both sides are in reference order, so walk them together to find the first difference.
+    let expected = entries(expected)?;
+    let actual = sort_stations(stations);
+    let (mut expected, mut actual) = (
+        expected.into_iter().peekable(),
+        actual.into_iter().peekable(),
+    );
+    loop {
+        match (expected.peek(), actual.peek()) {
+            (Some(&(name, values)), Some(&(station, stat))) if name == station => {
+                let stat = stat.display(config.precision).to_string();
+                if stat.as_bytes() != values {
+                    bail!(
+                        "Station \"{}\" differs: expected {}, got {stat}",
+                        name.escape_ascii(),
+                        values.escape_ascii()
+                    );
+                }
+                expected.next();
+                actual.next();
+            }
+            (Some(&(name, values)), next)
+                if next.is_none_or(|&(station, _)| reference_order(name, station).is_lt()) =>
+            {
+                bail!(
+                    "Station \"{}\" is missing, expected {}",
+                    name.escape_ascii(),
+                    values.escape_ascii()
+                );
+            }
+            (_, Some(&(station, stat))) => bail!(
+                "Station \"{}\" is unexpected, got {}",
+                station.escape_ascii(),
+                stat.display(config.precision)
+            ),
+            (_, None) => bail!("Output differs from the reference only in formatting"),
+        }
+    }
+}
+
+/// Splits a `{name=min/mean/max, ...}` map into its names and values, in order.
+///
+/// Names may contain `, ` or `=` themselves, so a piece without `=` is joined to the next one and
+/// the value starts after the last `=`.
+pub fn entries(map: &[u8]) -> Result<Vec<(&[u8], &[u8])>> {
+    let inner = map
+        .strip_prefix(b"{")
+        .and_then(|map| map.strip_suffix(b"}"))
+        .context("Reference map isn't enclosed in braces")?;
+    let mut entries = Vec::new();
+    let mut start = 0;
+    for end in memmem::find_iter(inner, b", ").chain([inner.len()]) {
+        let entry = &inner[start..end];
+        let Some(eq) = entry.iter().rposition(|&byte| byte == b'=') else {
+            continue;
+        };
+        entries.push((&entry[..eq], &entry[eq + 1..]));
+        start = end + 2;
+    }
+    if start < inner.len() {
+        bail!("Reference map ends with an entry that has no value");
+    }
+    Ok(entries)
+}
@@ -0,0 +1,188 @@
+use std::{cell::Cell, collections::HashMap, hash::BuildHasher};
+
+use gxhash::GxBuildHasher;
+
+use crate::Stat;
+
+/// Receives every `(station, value)` pair parsed from a chunk and accumulates whatever it likes.
+///
+/// Each chunk is scanned into its own visitor, made with [`Default`], and the visitors of all
+/// chunks are then folded together with [`LineVisitor::merge`]. The scanning loop is generic over
+/// the visitor, so every implementation gets its own monomorphized hot loop.
+pub trait LineVisitor<'a>: Default + Send {
+    fn visit(&mut self, station: &'a [u8], value: i16);
+    /// Like [`LineVisitor::visit`], for runs that never report the first and last values, so a
+    /// visitor can skip keeping them.
+    #[inline]
+    fn visit_unordered(&mut self, station: &'a [u8], value: i16) {
+        self.visit(station, value);
+    }
+    /// Makes room for a station before any of its values arrive, so visiting it never grows the
+    /// visitor.
+    fn seed(&mut self, station: &'a [u8]);
+    /// Folds in the visitor of a later chunk.
+    fn merge(&mut self, other: Self);
+    /// How many distinct stations it has seen. It's checked after every line under
+    /// `--max-stations`, so it should be cheap.
+    fn stations(&self) -> usize;
+    /// The name of every station it has seen.
+    fn names(&self) -> impl Iterator<Item = &'a [u8]>;
+}
+
+/// Builds a [`Stat`] per station, hashing names with `S`.
+pub struct StatVisitor<'a, S = GxBuildHasher> {
+    pub stations: HashMap<&'a [u8], Stat, S>,
+    /// How many times `stations` grew, across every visitor merged into this one.
+    pub resizes: u32,
+}
+
+impl<S: BuildHasher + Default> Default for StatVisitor<'_, S> {
+    fn default() -> Self {
+        HashMap::with_capacity_and_hasher(10_000, S::default()).into()
+    }
+}
+
+impl<'a, S> From<HashMap<&'a [u8], Stat, S>> for StatVisitor<'a, S> {
+    fn from(stations: HashMap<&'a [u8], Stat, S>) -> Self {
+        Self {
+            stations,
+            resizes: 0,
+        }
+    }
+}
+
+impl<'a, S: BuildHasher> StatVisitor<'a, S> {
+    /// Inserts a station that isn't in the map yet, counting whether that grew it.
+    fn insert(&mut self, station: &'a [u8], stat: Stat) {
+        let capacity = self.stations.capacity();
+        self.stations.insert(station, stat);
+        self.resizes += u32::from(self.stations.capacity() != capacity);
+    }
+
+    /// Folds in the stat of a station from later lines.
+    fn merge_stat(&mut self, station: &'a [u8], stat: Stat) {
+        match self.stations.get_mut(station) {
+            Some(existing) => existing.merge(&stat),
+            None => self.insert(station, stat),
+        }
+    }
+}
+
+impl<'a, S: BuildHasher + Default + Send> LineVisitor<'a> for StatVisitor<'a, S> {
+    #[inline]
+    fn visit(&mut self, station: &'a [u8], value: i16) {
+        match self.stations.get_mut(station) {
+            Some(r) => r.update(value),
+            None => self.insert(station, Stat::new(value)),
+        }
+    }
+
+    #[inline]
+    fn visit_unordered(&mut self, station: &'a [u8], value: i16) {
+        match self.stations.get_mut(station) {
+            Some(r) => r.update_unordered(value),
+            None => self.insert(station, Stat::new(value)),
+        }
+    }
+
+    /// Seeded stations hold an empty [`Stat`], which merges as the identity.
+    fn seed(&mut self, station: &'a [u8]) {
+        if !self.stations.contains_key(station) {
+            self.insert(station, Stat::default());
+        }
+    }
+
+    /// The result doesn't depend on the order stations arrive in, so gxhash's random seed can't
+    /// affect it: min/max are commutative and totals are exact integer sums, with floats only used
+    /// for display. Only the first and last values depend on `other` holding later lines, which
+    /// [`scan`](crate::scan) guarantees by merging chunks in file order.
+    fn merge(&mut self, other: Self) {
+        self.resizes += other.resizes;
+        for (station, stat) in other.stations {
+            self.merge_stat(station, stat);
+        }
+    }
+
+    fn stations(&self) -> usize {
+        self.stations.len()
+    }
+
+    fn names(&self) -> impl Iterator<Item = &'a [u8]> {
+        self.stations.keys().copied()
+    }
+}
+
+/// Builds a [`Stat`] per station from input sorted by station, accumulating each run of lines for
+/// one station without hashing and only folding it into a [`StatVisitor`] when the run ends.
+///
+/// Input that isn't sorted still comes out right, since a station's later runs merge into its
+/// earlier ones, but hashes once per run instead of once per station.
+#[derive(Default)]
+pub struct SortedVisitor<'a> {
+    run: Option<(&'a [u8], Stat)>,
+    /// Whether the current run's station is missing from `stations`, once something has asked.
+    /// The map can't change during a run, so one lookup answers for the whole run.
+    run_is_new: Cell<Option<bool>>,
+    stations: StatVisitor<'a>,
+}
+
+impl<'a> SortedVisitor<'a> {
+    /// Ends the current run, leaving every station in the map.
+    pub fn finish(mut self) -> StatVisitor<'a> {
+        self.flush();
+        self.stations
+    }
+
+    fn flush(&mut self) {
+        if let Some((station, stat)) = self.run.take() {
+            self.stations.merge_stat(station, stat);
+        }
+        self.run_is_new.set(None);
+    }
+
+    fn run_is_new(&self) -> bool {
+        let Some((station, _)) = self.run else {
+            return false;
+        };
+        let is_new = self
+            .run_is_new
+            .get()
+            .unwrap_or_else(|| !self.stations.stations.contains_key(station));
+        self.run_is_new.set(Some(is_new));
+        is_new
+    }
+}
+
+impl<'a> LineVisitor<'a> for SortedVisitor<'a> {
+    #[inline]
+    fn visit(&mut self, station: &'a [u8], value: i16) {
+        match &mut self.run {
+            Some((current, stat)) if *current == station => stat.update(value),
+            _ => {
+                self.flush();
+                self.run = Some((station, Stat::new(value)));
+            }
+        }
+    }
+
+    fn seed(&mut self, station: &'a [u8]) {
+        self.stations.seed(station);
+    }
+
+    /// Stations spanning the boundary between the chunks are stitched together here.
+    fn merge(&mut self, mut other: Self) {
+        self.flush();
+        other.flush();
+        self.stations.merge(other.stations);
+    }
+
+    fn stations(&self) -> usize {
+        self.stations.stations() + usize::from(self.run_is_new())
+    }
+
+    fn names(&self) -> impl Iterator<Item = &'a [u8]> {
+        let run = self.run.map(|(station, _)| station);
+        let run = run.filter(|_| self.run_is_new());
+        self.stations.names().chain(run)
+    }
+}
@@ -0,0 +1,182 @@
+use std::{collections::BTreeMap, num::NonZero, path::PathBuf};
+
+use anyhow::{Context, Result};
+use gxhash::{HashMap, HashMapExt};
+#[cfg(feature = "rayon")]
+use rayon::iter::ParallelIterator;
+
+use crate::{
+    Stat,
+    config::{Config, Recovery},
+    error::ParseError,
+    fields, filtered_out,
+    input::map_files,
+    input_chunks,
+    output::write_windows,
+    par_iter, parse_integer, parse_reading, records, slices,
+};
+
+/// What a chunk made of its lines: how many there were, how many were skipped as malformed, and
+/// a stat per station and window, keyed by the window's first second.
+type ChunkWindows<'a> = (u64, u64, HashMap<(&'a [u8], i64), Stat>);
+
+/// A record's station name, timestamp and value, all unparsed.
+type Fields<'a> = (&'a [u8], &'a [u8], &'a [u8]);
+
+/// A record's station name, timestamp in seconds and value.
+type Reading<'a> = (&'a [u8], i64, i16);
+
+/// Aggregates the inputs per station and per `width`-second window of the timestamp in each
+/// record, then writes one NDJSON line for each station and window.
+///
+/// Windows are aligned to multiples of `width` seconds since the Unix epoch. Everything after
+/// aggregating is skipped, so options that act on the finished stations, like `--min-count`, don't
+/// apply.
+pub fn time_windows(
+    paths: &[PathBuf],
+    max_open: usize,
+    parts: NonZero<usize>,
+    width: NonZero<u32>,
+    config: &Config,
+) -> Result<()> {
+    let mut windows = HashMap::<Box<[u8]>, BTreeMap<i64, Stat>>::with_capacity(10_000);
+    let mut lines = 0;
+    let mut malformed_lines = 0;
+    for wave in paths.chunks(max_open) {
+        let maps = map_files(wave, config)?;
+        let chunks = slices(&maps, config)
+            .into_iter()
+            .flat_map(|data| input_chunks(data, parts, config))
+            .collect::<Vec<_>>();
+        let results = par_iter(chunks)
+            .map(|chunk| window_chunk(chunk, width, config))
+            .collect::<Result<Vec<_>, _>>()
+            .context("One or more chunks could not be processed")?;
+        for (chunk_lines, chunk_malformed, chunk_windows) in results {
+            lines += chunk_lines;
+            malformed_lines += chunk_malformed;
+            for ((station, start), stat) in chunk_windows {
+                match windows.get_mut(station) {
+                    Some(station_windows) => station_windows.entry(start).or_default().merge(&stat),
+                    None => {
+                        windows.insert(station.into(), BTreeMap::from([(start, stat)]));
+                    }
+                }
+            }
+        }
+    }
+    if let Some(log) = &config.error_log {
+        log.finish()?;
+    }
+    if !config.quiet {
+        eprintln!("Total lines processed: {lines}");
+        if malformed_lines > 0 {
+            eprintln!("Malformed lines skipped: {malformed_lines}");
+        }
+    }
+    write_windows(&windows, config)
+}
+
+/// Aggregates the lines of `data` per station and `width`-second window.
+fn window_chunk<'a>(
+    data: &'a [u8],
+    width: NonZero<u32>,
+    config: &Config,
+) -> Result<ChunkWindows<'a>, ParseError> {
+    let width = i64::from(width.get());
+    let mut windows = HashMap::with_capacity(10_000);
+    let mut lines = 0;
+    let mut malformed = 0;
+    for line in records(data, &config.record_delim) {
+        lines += 1;
+        let (station, timestamp, value) = match parse_record(line, config) {
+            Ok(Some(record)) => record,
+            Ok(None) => continue,
+            Err(err) => {
+                // A fixed value still needs the line's timestamp to land in a window.
+                let timestamp = split_record(line, config)
+                    .ok()
+                    .and_then(|(_, timestamp, _)| parse_timestamp(timestamp).ok());
+                match (config.on_malformed.recover(line, &err, config), timestamp) {
+                    (Recovery::Abort, _) => return Err(err),
+                    (Recovery::Fix(station, value), Some(timestamp)) => {
+                        if filtered_out(station, config) {
+                            continue;
+                        }
+                        (station, timestamp, value)
+                    }
+                    (Recovery::Skip | Recovery::Fix(..), _) => {
+                        malformed += 1;
+                        if let Some(log) = &config.error_log {
+                            log.log(line);
+                        }
+                        continue;
+                    }
+                }
+            }
+        };
+        let start = timestamp.div_euclid(width) * width;
+        windows
+            .entry((station, start))
+            .or_insert_with(Stat::default)
+            .update(value);
+    }
+    Ok((lines, malformed, windows))
+}
+
+/// Parses a record into its station, timestamp and value, or `None` if the station is filtered
+/// out or the value ignored.
+fn parse_record<'a>(line: &'a [u8], config: &Config) -> Result<Option<Reading<'a>>, ParseError> {
+    let (station, timestamp, value) = split_record(line, config)?;
+    if filtered_out(station, config) {
+        return Ok(None);
+    }
+    let timestamp = parse_timestamp(timestamp)?;
+    Ok(parse_reading(value, config)?.map(|value| (station, timestamp, value)))
+}
+
+/// Splits a record into its station name, timestamp and unparsed value. The value is the
+/// `--value-column`th, counting from 0, of the fields that are neither the name nor the timestamp.
+fn split_record<'a>(line: &'a [u8], config: &Config) -> Result<Fields<'a>, ParseError> {
+    let name_index = config.name_column.unwrap_or(0);
+    let timestamp_index = config.timestamp_column.unwrap_or(1);
+    // The value column skips over the name's and the timestamp's.
+    let mut value_index = config.value_column.unwrap_or(0);
+    for skipped in [
+        name_index.min(timestamp_index),
+        name_index.max(timestamp_index),
+    ] {
+        value_index += usize::from(skipped <= value_index);
+    }
+    let (mut name, mut timestamp, mut value) = (None, None, None);
+    let needed = name_index.max(timestamp_index).max(value_index) + 1;
+    for (index, field) in fields(line, &config.separator).enumerate().take(needed) {
+        if index == name_index {
+            name = Some(field);
+        } else if index == timestamp_index {
+            timestamp = Some(field);
+        } else if index == value_index {
+            value = Some(field);
+        }
+    }
+    let name = name.ok_or(ParseError::MissingField { index: name_index })?;
+    if let Some(max) = config.max_name_len
+        && name.len() > max
+    {
+        return Err(ParseError::NameTooLong { len: name.len() });
+    }
+    Ok((
+        name,
+        timestamp.ok_or(ParseError::MissingField {
+            index: timestamp_index,
+        })?,
+        value.ok_or(ParseError::MissingField { index: value_index })?,
+    ))
+}
+
+/// Parses a timestamp in whole seconds since the Unix epoch.
+fn parse_timestamp(bytes: &[u8]) -> Result<i64, ParseError> {
+    parse_integer(bytes.trim_ascii()).ok_or_else(|| ParseError::InvalidTimestamp {
+        bytes: bytes.into(),
+    })
+}